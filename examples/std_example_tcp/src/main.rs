@@ -1,6 +1,6 @@
 use std::{
     io::{Read, Write},
-    net::{Ipv4Addr, SocketAddr, SocketAddrV4, TcpListener, TcpStream, UdpSocket},
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4, TcpListener, TcpStream},
     thread,
 };
 
@@ -20,7 +20,10 @@ fn main() {
 fn handler(mut stream: TcpStream) {
     let mut read: [u8; 4096] = [0; 4096];
     loop {
-        stream.read(&mut read).unwrap();
-        stream.write(&mut read).unwrap();
+        let len = stream.read(&mut read).unwrap();
+        if len == 0 {
+            return;
+        }
+        stream.write_all(&read[..len]).unwrap();
     }
 }
@@ -22,7 +22,10 @@ async fn main() {
 async fn handler(mut stream: TcpStream) {
     let mut read: [u8; 4096] = [0; 4096];
     loop {
-        stream.read(&mut read).await.unwrap();
-        stream.write(&mut read).await.unwrap();
+        let len = stream.read(&mut read).await.unwrap();
+        if len == 0 {
+            return;
+        }
+        stream.write_all(&read[..len]).await.unwrap();
     }
 }
@@ -29,7 +29,7 @@ async fn main() {
 async fn handler(socket: Arc<UdpSocket>) {
     let mut read: [u8; 4096] = [0; 4096];
     loop {
-        let addr = socket.recv_from(&mut read).await.unwrap().1;
-        socket.send_to(&mut read, addr).await.unwrap();
+        let (len, addr) = socket.recv_from(&mut read).await.unwrap();
+        socket.send_to(&read[..len], addr).await.unwrap();
     }
 }
@@ -0,0 +1,156 @@
+use std::{net::SocketAddr, num::NonZero, sync::Arc, time::Duration};
+
+use liburing_rs::__kernel_timespec;
+
+use crate::{
+    framed::Framed,
+    h2::Http2,
+    memcached::Memcached,
+    redis::Redis,
+    search,
+    settings::{self, Protocol, Settings},
+    stats::Statistics,
+    worker::{self, Round},
+    workload::{Echo, Workload, WorkloadKind},
+    ws::WebSocket,
+};
+
+/// Call given `_with` method with workload the settings pick, every kind gets its own copy of
+/// the workers
+macro_rules! dispatch {
+    ($self:ident.$method:ident) => {
+        match $self.settings.workload {
+            WorkloadKind::Echo => $self.$method(&Echo::default()),
+            WorkloadKind::Redis => $self.$method(&Redis::default()),
+            WorkloadKind::Memcached => $self.$method(&Memcached::default()),
+            WorkloadKind::WebSocket => $self.$method(&WebSocket::default()),
+            WorkloadKind::Http2 | WorkloadKind::Grpc => $self.$method(&Http2::default()),
+            WorkloadKind::Length | WorkloadKind::Delimited => $self.$method(&Framed::default()),
+        }
+    };
+}
+
+/// One benchmark setup that can be run any number of times
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use ultimatum::{Benchmark, Protocol};
+///
+/// let stats = Benchmark::new("127.0.0.1:6664".parse().unwrap())
+///     .protocol(Protocol::Udp)
+///     .connections(64)
+///     .duration(Duration::from_secs(2))
+///     .run();
+/// println!("{} right results", stats.successful_returns());
+/// ```
+#[derive(Clone, Debug)]
+pub struct Benchmark {
+    settings: Settings,
+}
+
+impl Benchmark {
+    /// Command line defaults aimed at given target
+    pub fn new(target: SocketAddr) -> Self {
+        Self::from_settings(Settings {
+            target,
+            ..Settings::default()
+        })
+    }
+
+    pub fn from_settings(settings: Settings) -> Self {
+        Self { settings }
+    }
+
+    pub fn protocol(mut self, proto: Protocol) -> Self {
+        self.settings.proto = proto;
+        self
+    }
+
+    pub fn connections(mut self, connections: usize) -> Self {
+        self.settings.connections = connections;
+        self
+    }
+
+    pub fn threads(mut self, threads: NonZero<usize>) -> Self {
+        self.settings.threads = threads;
+        self
+    }
+
+    /// Length of the measured window, ignored when `requests` is set
+    pub fn duration(mut self, duration: Duration) -> Self {
+        self.settings.burn_time = __kernel_timespec::from(duration);
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.settings.timeout = Some(__kernel_timespec::from(timeout));
+        self
+    }
+
+    /// Offered requests per second across all connections
+    pub fn rate(mut self, rate: f64) -> Self {
+        assert!(rate > 0.0, "Rate has to be a positive number");
+        self.settings.rate = Some(rate);
+        self
+    }
+
+    /// End the run after this many right results instead of after duration
+    pub fn requests(mut self, requests: u64) -> Self {
+        self.settings.requests = Some(requests);
+        self
+    }
+
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    /// Anything the builder has no method for
+    pub fn settings_mut(&mut self) -> &mut Settings {
+        &mut self.settings
+    }
+
+    /// Run once with workload picked in settings and return merged statistics of all threads,
+    /// blocks until done
+    pub fn run(&self) -> Statistics {
+        dispatch!(self.run_with)
+    }
+
+    /// Same as `run` speaking protocol of given workload
+    pub fn run_with<W: Workload>(&self, workload: &W) -> Statistics {
+        let settings = self.enter();
+        worker::run(
+            Round {
+                burn_time: settings.burn_time,
+                rate: settings.rate,
+                requests: settings.requests,
+            },
+            workload,
+        )
+    }
+
+    /// Search for highest rate within `Settings::search`, printing every round
+    pub fn search(&self) -> Option<f64> {
+        dispatch!(self.search_with)
+    }
+
+    pub fn search_with<W: Workload>(&self, workload: &W) -> Option<f64> {
+        self.enter();
+        search::search(workload)
+    }
+
+    /// Resolve a copy of settings and make it current on the calling thread
+    ///
+    /// Workers get their own handle on the copy, it is freed once they are joined and the
+    /// next run is entered
+    fn enter(&self) -> Arc<Settings> {
+        if let Err(err) = self.settings.check() {
+            panic!("{err}");
+        }
+
+        let mut settings = self.settings.clone();
+        settings.resolve();
+        let settings = Arc::new(settings);
+        settings::enter(settings.clone());
+        settings
+    }
+}
@@ -0,0 +1,329 @@
+use std::io::{Cursor, Write};
+
+use crate::{
+    keys::SplitMix,
+    settings::{get_settings, Settings},
+    validate::Failure,
+    workload::{Frame, Workload, WorkloadKind},
+};
+
+/// Length in front of every `-p length` message, it counts payload bytes only
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LengthPrefix {
+    /// Bytes of the length itself, 1, 2, 4 or 8
+    pub width: usize,
+    pub big_endian: bool,
+}
+
+impl Default for LengthPrefix {
+    fn default() -> Self {
+        Self {
+            width: 4,
+            big_endian: true,
+        }
+    }
+}
+
+impl LengthPrefix {
+    /// Parse `u8`, `u16`, `u32` or `u64` with optional `be` or `le`, big endian by default
+    pub fn parse(value: &str) -> Option<Self> {
+        let (bits, big_endian) = match value.len().checked_sub(2).map(|x| value.split_at(x)) {
+            Some((bits, "be")) => (bits, true),
+            Some((bits, "le")) => (bits, false),
+            _ => (value, true),
+        };
+        let width = match bits {
+            "u8" => 1,
+            "u16" => 2,
+            "u32" => 4,
+            "u64" => 8,
+            _ => return None,
+        };
+        Some(Self { width, big_endian })
+    }
+
+    /// Longest payload the prefix can tell
+    pub fn max_len(&self) -> u64 {
+        u64::MAX >> (64 - 8 * self.width)
+    }
+
+    fn write(&self, out: &mut Cursor<&mut [u8]>, len: usize) -> std::io::Result<()> {
+        let len = len as u64;
+        if self.big_endian {
+            out.write_all(&len.to_be_bytes()[8 - self.width..])
+        } else {
+            out.write_all(&len.to_le_bytes()[..self.width])
+        }
+    }
+
+    fn read(&self, buf: &[u8]) -> Option<usize> {
+        let prefix = buf.get(..self.width)?;
+        let mut bytes = [0; 8];
+        let len = if self.big_endian {
+            bytes[8 - self.width..].copy_from_slice(prefix);
+            u64::from_be_bytes(bytes)
+        } else {
+            bytes[..self.width].copy_from_slice(prefix);
+            u64::from_le_bytes(bytes)
+        };
+        Some(len as usize)
+    }
+}
+
+/// Parse delimiter given on command line, `\n`, `\r`, `\t`, `\0`, `\\` and `\xNN` are escapes
+pub fn parse_delimiter(value: &str) -> Option<Vec<u8>> {
+    let mut delimiter = Vec::new();
+    let mut rest = value.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        rest = tail;
+        if byte != b'\\' {
+            delimiter.push(byte);
+            continue;
+        }
+
+        let (&escape, tail) = rest.split_first()?;
+        rest = tail;
+        delimiter.push(match escape {
+            b'n' => b'\n',
+            b'r' => b'\r',
+            b't' => b'\t',
+            b'0' => 0,
+            b'\\' => b'\\',
+            b'x' => {
+                let hex = std::str::from_utf8(rest.get(..2)?).ok()?;
+                rest = &rest[2..];
+                u8::from_str_radix(hex, 16).ok()?
+            }
+            _ => return None,
+        });
+    }
+    (!delimiter.is_empty()).then_some(delimiter)
+}
+
+/// Bytes delimited payload is made of, it must not contain any byte of its delimiter
+pub fn alphabet(delimiter: &[u8]) -> Vec<u8> {
+    (b'0'..=b'9')
+        .chain(b'a'..=b'z')
+        .filter(|x| !delimiter.contains(x))
+        .collect()
+}
+
+/// Bytes of every request given settings make
+pub fn request_len(settings: &Settings) -> usize {
+    match settings.workload {
+        WorkloadKind::Delimited => settings.value_size + settings.delimiter.len(),
+        _ => settings.length_prefix.width + settings.value_size,
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Framing {
+    Length(LengthPrefix),
+    Delimiter(Vec<u8>),
+}
+
+impl Default for Framing {
+    fn default() -> Self {
+        Self::Length(LengthPrefix::default())
+    }
+}
+
+/// Messages cut out of the stream by a length prefix or a delimiter, servers echo them back
+#[derive(Clone, Debug, Default)]
+pub struct Framed {
+    framing: Framing,
+    rng: SplitMix,
+    /// Bytes payload is made of
+    alphabet: Vec<u8>,
+    payload: Vec<u8>,
+}
+
+impl Workload for Framed {
+    fn for_connection(&self, connection: u64) -> Self {
+        let settings = get_settings();
+        let (framing, alphabet) = match settings.workload {
+            WorkloadKind::Delimited => (
+                Framing::Delimiter(settings.delimiter.clone()),
+                alphabet(&settings.delimiter),
+            ),
+            _ => (Framing::Length(settings.length_prefix), (0..=255).collect()),
+        };
+
+        Self {
+            framing,
+            rng: SplitMix(connection),
+            alphabet,
+            payload: vec![0; settings.value_size],
+        }
+    }
+
+    fn request(&mut self, buf: &mut [u8]) -> usize {
+        for byte in self.payload.iter_mut() {
+            *byte = self.alphabet[self.rng.next_u64() as usize % self.alphabet.len()];
+        }
+
+        let mut out = Cursor::new(buf);
+        let written = match &self.framing {
+            Framing::Length(prefix) => prefix
+                .write(&mut out, self.payload.len())
+                .and_then(|_| out.write_all(&self.payload)),
+            Framing::Delimiter(delimiter) => out
+                .write_all(&self.payload)
+                .and_then(|_| out.write_all(delimiter)),
+        };
+        written.expect("Framed message does not fit into request buffer");
+        out.position() as usize
+    }
+
+    fn frame(&self, _request: &[u8], received: &[u8]) -> Frame {
+        let len = match &self.framing {
+            Framing::Length(prefix) => prefix
+                .read(received)
+                .and_then(|len| len.checked_add(prefix.width))
+                .filter(|&total| total <= received.len()),
+            Framing::Delimiter(delimiter) => received
+                .windows(delimiter.len())
+                .position(|x| x == delimiter.as_slice())
+                .map(|at| at + delimiter.len()),
+        };
+
+        match len {
+            Some(len) => Frame::Complete(len),
+            None => Frame::Incomplete,
+        }
+    }
+
+    fn validate(&mut self, request: &[u8], response: &[u8]) -> Result<(), Failure> {
+        if response.len() != request.len() {
+            Err(Failure::Length)
+        } else if response != request {
+            Err(Failure::Content)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn length_prefixes() {
+        for (value, width, big_endian) in [
+            ("u8", 1, true),
+            ("u16", 2, true),
+            ("u32be", 4, true),
+            ("u32le", 4, false),
+            ("u64le", 8, false),
+        ] {
+            assert_eq!(
+                LengthPrefix::parse(value),
+                Some(LengthPrefix { width, big_endian })
+            );
+        }
+        for value in ["", "be", "u24", "u32xe", "U32"] {
+            assert_eq!(LengthPrefix::parse(value), None, "{value}");
+        }
+
+        assert_eq!(LengthPrefix::parse("u8").unwrap().max_len(), 0xff);
+        assert_eq!(LengthPrefix::parse("u16").unwrap().max_len(), 0xffff);
+        assert_eq!(LengthPrefix::parse("u64").unwrap().max_len(), u64::MAX);
+    }
+
+    #[test]
+    fn length_round_trip() {
+        for (value, bytes) in [
+            ("u16be", &[0x01, 0x02][..]),
+            ("u16le", &[0x02, 0x01]),
+            ("u32be", &[0, 0, 0x01, 0x02]),
+            ("u64le", &[0x02, 0x01, 0, 0, 0, 0, 0, 0]),
+        ] {
+            let prefix = LengthPrefix::parse(value).unwrap();
+            let mut buf = [0; 8];
+            let mut out = Cursor::new(&mut buf[..]);
+            prefix.write(&mut out, 0x0102).unwrap();
+            assert_eq!(out.position() as usize, prefix.width);
+            assert_eq!(&buf[..prefix.width], bytes, "{value}");
+            assert_eq!(prefix.read(bytes), Some(0x0102));
+            assert_eq!(prefix.read(&bytes[..bytes.len() - 1]), None);
+        }
+    }
+
+    #[test]
+    fn delimiters() {
+        for (value, delimiter) in [
+            ("\\n", &b"\n"[..]),
+            ("\\r\\n", b"\r\n"),
+            ("END", b"END"),
+            ("\\0\\t\\\\", b"\0\t\\"),
+            ("\\x00\\xfF", b"\x00\xff"),
+        ] {
+            assert_eq!(
+                parse_delimiter(value).as_deref(),
+                Some(delimiter),
+                "{value}"
+            );
+        }
+        for value in ["", "\\", "\\q", "\\x", "\\x0", "\\xzz"] {
+            assert_eq!(parse_delimiter(value), None, "{value}");
+        }
+    }
+
+    #[test]
+    fn alphabet_leaves_delimiter_out() {
+        assert_eq!(alphabet(b"\n").len(), 36);
+        assert_eq!(alphabet(b"a0\n").len(), 34);
+        let everything: Vec<u8> = (b'0'..=b'9').chain(b'a'..=b'z').collect();
+        assert!(alphabet(&everything).is_empty());
+    }
+
+    #[test]
+    fn length_frames() {
+        let settings = Settings {
+            workload: WorkloadKind::Length,
+            length_prefix: LengthPrefix::parse("u16").unwrap(),
+            value_size: 5,
+            ..Settings::default()
+        };
+        let len = request_len(&settings);
+        let mut framed: Framed = testing::connection(settings);
+        let mut buf = [0; 64];
+        assert_eq!(framed.request(&mut buf), len);
+        assert_eq!(&buf[..2], [0, 5]);
+
+        let response = &buf[..len];
+        testing::assert_waits_for_all(&framed, response, response);
+        assert_eq!(framed.frame(response, response), Frame::Complete(len));
+        assert_eq!(
+            framed.frame(response, &buf[..len + 1]),
+            Frame::Complete(len)
+        );
+        assert_eq!(framed.validate(response, response), Ok(()));
+    }
+
+    #[test]
+    fn delimited_frames() {
+        let settings = Settings {
+            workload: WorkloadKind::Delimited,
+            delimiter: b"\r\n".to_vec(),
+            value_size: 16,
+            ..Settings::default()
+        };
+        let len = request_len(&settings);
+        let mut framed: Framed = testing::connection(settings);
+        let mut buf = [0; 64];
+        assert_eq!(framed.request(&mut buf), len);
+
+        let request = buf[..len].to_vec();
+        assert!(request[..16].iter().all(|x| x.is_ascii_alphanumeric()));
+        testing::assert_waits_for_all(&framed, &request, &request);
+        assert_eq!(framed.frame(&request, &request), Frame::Complete(len));
+
+        let mut wrong = request.clone();
+        wrong[0] ^= 1;
+        assert_eq!(framed.validate(&request, &wrong), Err(Failure::Content));
+        assert_eq!(framed.validate(&request, b"\r\n"), Err(Failure::Length));
+    }
+}
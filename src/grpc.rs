@@ -0,0 +1,49 @@
+/// Status codes from OK up to UNAUTHENTICATED, anything above is counted as UNKNOWN
+pub const STATUSES: usize = 17;
+const UNKNOWN: usize = 2;
+
+#[derive(Clone, Debug, Default)]
+pub struct GrpcOptions {
+    /// Serialized protobuf message every call sends, empty one by default
+    pub payload: Vec<u8>,
+}
+
+/// Length prefixed message as it goes into DATA frames, never compressed
+pub fn message(payload: &[u8]) -> Vec<u8> {
+    let mut message = Vec::with_capacity(5 + payload.len());
+    message.push(0);
+    message.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    message.extend_from_slice(payload);
+    message
+}
+
+/// Status code out of `grpc-status` value, unknown and malformed codes are UNKNOWN
+pub fn parse_status(value: &[u8]) -> usize {
+    std::str::from_utf8(value)
+        .ok()
+        .and_then(|x| x.parse::<usize>().ok())
+        .filter(|&x| x < STATUSES)
+        .unwrap_or(UNKNOWN)
+}
+
+pub fn status_name(status: usize) -> &'static str {
+    [
+        "OK",
+        "CANCELLED",
+        "UNKNOWN",
+        "INVALID_ARGUMENT",
+        "DEADLINE_EXCEEDED",
+        "NOT_FOUND",
+        "ALREADY_EXISTS",
+        "PERMISSION_DENIED",
+        "RESOURCE_EXHAUSTED",
+        "FAILED_PRECONDITION",
+        "ABORTED",
+        "OUT_OF_RANGE",
+        "UNIMPLEMENTED",
+        "INTERNAL",
+        "UNAVAILABLE",
+        "DATA_LOSS",
+        "UNAUTHENTICATED",
+    ][status]
+}
@@ -0,0 +1,714 @@
+use std::{
+    io::{Cursor, Write},
+    time::Instant,
+};
+
+use crate::{
+    grpc,
+    hpack::{self, Decoder},
+    settings::{get_settings, Settings},
+    stats::Statistics,
+    validate::Failure,
+    workload::{Frame, Workload, WorkloadKind},
+};
+
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+const FRAME_HEADER: usize = 9;
+
+const DATA: u8 = 0x0;
+const HEADERS: u8 = 0x1;
+const RST_STREAM: u8 = 0x3;
+const SETTINGS: u8 = 0x4;
+const PING: u8 = 0x6;
+const GOAWAY: u8 = 0x7;
+const WINDOW_UPDATE: u8 = 0x8;
+const CONTINUATION: u8 = 0x9;
+
+const END_STREAM: u8 = 0x1;
+const ACK: u8 = 0x1;
+const END_HEADERS: u8 = 0x4;
+const PADDED: u8 = 0x8;
+const PRIORITY: u8 = 0x20;
+
+const SETTINGS_ENABLE_PUSH: u16 = 0x2;
+const SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 0x3;
+const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+/// Largest flow control window, we open it all the way and never have to think about it again
+const MAX_WINDOW: u32 = 0x7fff_ffff;
+const DEFAULT_WINDOW: u32 = 65535;
+const MAX_STREAM: u32 = 0x7fff_ffff;
+
+#[derive(Clone, Debug)]
+pub struct Http2Options {
+    /// Streams kept open on every connection, a new one opens as soon as one ends
+    pub streams: usize,
+}
+
+impl Default for Http2Options {
+    fn default() -> Self {
+        Self { streams: 8 }
+    }
+}
+
+/// GET requests or unary gRPC calls over HTTP/2 with prior knowledge (h2c), several streams
+/// in flight at once
+///
+/// An exchange ends once any stream ended, the next request opens new streams in place of
+/// the ended ones so the connection always has as many in flight as it may. Every stream
+/// gets its own latency and is counted right or wrong on its own.
+#[derive(Clone, Debug, Default)]
+pub struct Http2 {
+    streams: usize,
+    /// Streams that may be open at once, `streams` or what the server allows if that is less
+    limit: usize,
+    /// HPACK block of every request, it is the same each time as nothing gets indexed
+    headers: Vec<u8>,
+    /// DATA every stream sends after its headers, none for GET
+    body: Vec<u8>,
+    /// Streams are gRPC calls, they have to end with `grpc-status` 0
+    grpc: bool,
+    decoder: Decoder,
+    next_stream: u32,
+    open: Vec<OpenStream>,
+    /// Streams that ended since last request and how many of them were right
+    ended: usize,
+    good: u64,
+    /// Bytes of the response that went through `read` since last `drain`
+    parsed: usize,
+    /// DATA frame whose payload is still coming in, it is counted as it streams so frames
+    /// bigger than the receive buffer go through
+    data: Option<DataFrame>,
+    /// Server sent GOAWAY or connection state is broken
+    reconnect: bool,
+    /// Header block of the stream whose HEADERS are waiting for CONTINUATION
+    block: Vec<u8>,
+    block_stream: u32,
+    block_ends_stream: bool,
+    /// Control frames owed to the server, they go out in front of next streams
+    settings_ack: bool,
+    ping: Option<[u8; 8]>,
+    /// DATA bytes received and not handed back to connection window yet
+    consumed: u32,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct OpenStream {
+    id: u32,
+    /// When the request that opened it was due, None until it is sent
+    started: Option<Instant>,
+    /// Answered with something other than 2xx or its call failed
+    failed: bool,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct DataFrame {
+    stream: u32,
+    /// Payload bytes not read yet
+    left: usize,
+    ends_stream: bool,
+}
+
+/// Header of one frame, payload follows it
+#[derive(Clone, Copy, Debug)]
+struct FrameHeader {
+    len: usize,
+    kind: u8,
+    flags: u8,
+    stream: u32,
+}
+
+impl Http2 {
+    fn new(settings: &Settings) -> Self {
+        let grpc = settings.workload == WorkloadKind::Grpc;
+
+        let mut headers = Vec::new();
+        // :method GET or POST and :scheme http
+        hpack::indexed(&mut headers, if grpc { 3 } else { 2 });
+        hpack::indexed(&mut headers, 6);
+        match settings.path.as_str() {
+            "/" => hpack::indexed(&mut headers, 4),
+            path => hpack::literal(&mut headers, 4, path.as_bytes()),
+        }
+        hpack::literal(&mut headers, 1, settings.target.to_string().as_bytes());
+
+        let mut body = Vec::new();
+        if grpc {
+            hpack::literal(&mut headers, 31, b"application/grpc");
+            hpack::literal_new_name(&mut headers, b"te", b"trailers");
+            body = grpc::message(&settings.grpc.payload);
+        }
+
+        let streams = settings.h2.streams.max(1);
+        Self {
+            streams,
+            limit: streams,
+            headers,
+            body,
+            grpc,
+            ..Self::default()
+        }
+    }
+
+    /// Bytes one stream takes to open
+    fn stream_len(&self) -> usize {
+        let body = match self.body.is_empty() {
+            true => 0,
+            false => FRAME_HEADER + self.body.len(),
+        };
+        FRAME_HEADER + self.headers.len() + body
+    }
+}
+
+/// Bytes of the longest request given settings make, control frames and every stream at once
+pub fn request_len(settings: &Settings) -> usize {
+    let h2 = Http2::new(settings);
+    // SETTINGS ACK, PING ACK and WINDOW_UPDATE
+    let control = FRAME_HEADER + (FRAME_HEADER + 8) + (FRAME_HEADER + 4);
+    control + h2.streams * h2.stream_len()
+}
+
+impl Workload for Http2 {
+    const MULTIPLEXED: bool = true;
+
+    fn for_connection(&self, _connection: u64) -> Self {
+        Self::new(get_settings())
+    }
+
+    fn handshake(&mut self, buf: &mut [u8]) -> usize {
+        // Every connection starts over, stream ids and header table included
+        *self = Self {
+            streams: self.streams,
+            limit: self.streams,
+            headers: std::mem::take(&mut self.headers),
+            body: std::mem::take(&mut self.body),
+            grpc: self.grpc,
+            next_stream: 1,
+            ..Self::default()
+        };
+
+        let mut settings = Vec::new();
+        for (id, value) in [
+            (SETTINGS_ENABLE_PUSH, 0),
+            (SETTINGS_INITIAL_WINDOW_SIZE, MAX_WINDOW),
+        ] {
+            settings.extend_from_slice(&id.to_be_bytes());
+            settings.extend_from_slice(&value.to_be_bytes());
+        }
+
+        let mut out = Cursor::new(buf);
+        let written = (|| {
+            out.write_all(PREFACE)?;
+            write_frame(&mut out, SETTINGS, 0, 0, &settings)?;
+            let increment = MAX_WINDOW - DEFAULT_WINDOW;
+            write_frame(&mut out, WINDOW_UPDATE, 0, 0, &increment.to_be_bytes())
+        })();
+        written.expect("HTTP/2 preface does not fit into request buffer");
+        out.position() as usize
+    }
+
+    fn handshake_frame(&self, received: &[u8]) -> Frame {
+        // Server preface is a SETTINGS frame, whatever came with it gets handled as well so
+        // wait until that is whole too
+        let mut offset = 0;
+        let mut settings = false;
+        while let Some(header) = parse_frame(&received[offset..]) {
+            offset += FRAME_HEADER + header.len;
+            match header.kind {
+                SETTINGS if header.flags & ACK == 0 => settings = true,
+                GOAWAY => return Frame::Invalid,
+                _ => {}
+            }
+        }
+
+        match settings && offset == received.len() {
+            true => Frame::Complete(offset),
+            false => Frame::Incomplete,
+        }
+    }
+
+    fn handshake_done(&mut self, response: &[u8]) {
+        // No stream is open yet, SETTINGS and PING is all there is to answer
+        let mut offset = 0;
+        while let Some(header) = parse_frame(&response[offset..]) {
+            let start = offset + FRAME_HEADER;
+            offset = start + header.len;
+            self.on_control_frame(header, &response[start..offset]);
+        }
+    }
+
+    fn request(&mut self, buf: &mut [u8]) -> usize {
+        let mut out = Cursor::new(buf);
+        let written = (|| {
+            if std::mem::take(&mut self.settings_ack) {
+                write_frame(&mut out, SETTINGS, ACK, 0, &[])?;
+            }
+            if let Some(ping) = self.ping.take() {
+                write_frame(&mut out, PING, ACK, 0, &ping)?;
+            }
+            // Streams have the whole window to themselves, only the connection needs it back
+            if self.consumed > 0 {
+                let increment = std::mem::take(&mut self.consumed);
+                write_frame(&mut out, WINDOW_UPDATE, 0, 0, &increment.to_be_bytes())?;
+            }
+
+            while self.open.len() < self.limit {
+                let id = self.next_stream;
+                if self.body.is_empty() {
+                    write_frame(
+                        &mut out,
+                        HEADERS,
+                        END_HEADERS | END_STREAM,
+                        id,
+                        &self.headers,
+                    )?;
+                } else {
+                    write_frame(&mut out, HEADERS, END_HEADERS, id, &self.headers)?;
+                    write_frame(&mut out, DATA, END_STREAM, id, &self.body)?;
+                }
+                self.open.push(OpenStream {
+                    id,
+                    started: None,
+                    failed: false,
+                });
+                self.next_stream += 2;
+            }
+            Ok::<_, std::io::Error>(())
+        })();
+        written.expect("HTTP/2 streams do not fit into request buffer");
+
+        self.ended = 0;
+        self.good = 0;
+        out.position() as usize
+    }
+
+    fn sent(&mut self, at: Instant) {
+        for stream in self.open.iter_mut().filter(|x| x.started.is_none()) {
+            stream.started = Some(at);
+        }
+    }
+
+    fn frame(&self, _request: &[u8], _received: &[u8]) -> Frame {
+        if self.reconnect {
+            return Frame::Reconnect;
+        }
+        // Next request would have no stream to open
+        if self.ended == 0 || self.open.len() >= self.limit {
+            return Frame::Incomplete;
+        }
+        // Stream ids ran out, new connection starts over from one
+        if self.next_stream > MAX_STREAM - 2 * self.streams as u32 {
+            return Frame::Reconnect;
+        }
+        // Everything read went out through `drain`, what is left starts the next exchange
+        Frame::Complete(0)
+    }
+
+    fn validate(&mut self, _request: &[u8], _response: &[u8]) -> Result<(), Failure> {
+        // Streams are counted one by one as they end
+        Ok(())
+    }
+
+    fn requests_per_exchange(&self) -> u64 {
+        self.good
+    }
+
+    fn read(&mut self, received: &[u8], now: Instant, stats: &mut Statistics) {
+        loop {
+            let rest = &received[self.parsed..];
+            if let Some(data) = self.data.as_mut() {
+                let take = data.left.min(rest.len());
+                if take == 0 && data.left > 0 {
+                    return;
+                }
+                data.left -= take;
+                self.parsed += take;
+                self.consumed += take as u32;
+                if data.left == 0 {
+                    let data = self.data.take().unwrap();
+                    if data.ends_stream {
+                        self.end_stream(data.stream, now, stats);
+                    }
+                }
+                continue;
+            }
+
+            let Some(header) = parse_header(rest) else {
+                return;
+            };
+            if header.kind == DATA {
+                self.parsed += FRAME_HEADER;
+                self.data = Some(DataFrame {
+                    stream: header.stream,
+                    left: header.len,
+                    ends_stream: header.flags & END_STREAM != 0,
+                });
+                continue;
+            }
+
+            // Any other frame is handled whole, they are small bar huge header blocks
+            let Some(payload) = rest.get(FRAME_HEADER..FRAME_HEADER + header.len) else {
+                return;
+            };
+            self.parsed += FRAME_HEADER + header.len;
+            self.on_frame(header, payload, now, stats);
+        }
+    }
+
+    fn drain(&mut self) -> usize {
+        std::mem::take(&mut self.parsed)
+    }
+}
+
+impl Http2 {
+    fn on_frame(
+        &mut self,
+        header: FrameHeader,
+        payload: &[u8],
+        now: Instant,
+        stats: &mut Statistics,
+    ) {
+        match header.kind {
+            HEADERS => {
+                let Some(fragment) = header_fragment(header.flags, payload) else {
+                    self.reconnect = true;
+                    return;
+                };
+                self.block.clear();
+                self.block.extend_from_slice(fragment);
+                self.block_stream = header.stream;
+                self.block_ends_stream = header.flags & END_STREAM != 0;
+                if header.flags & END_HEADERS != 0 {
+                    self.end_headers(now, stats);
+                }
+            }
+            CONTINUATION => {
+                self.block.extend_from_slice(payload);
+                if header.flags & END_HEADERS != 0 {
+                    self.end_headers(now, stats);
+                }
+            }
+            RST_STREAM => {
+                stats.increment_rst_streams();
+                self.fail_stream(header.stream);
+                self.end_stream(header.stream, now, stats);
+            }
+            GOAWAY => {
+                stats.increment_goaways();
+                self.reconnect = true;
+            }
+            _ => self.on_control_frame(header, payload),
+        }
+    }
+
+    fn on_control_frame(&mut self, header: FrameHeader, payload: &[u8]) {
+        match header.kind {
+            SETTINGS if header.flags & ACK == 0 => {
+                self.settings_ack = true;
+                for setting in payload.chunks_exact(6) {
+                    let id = u16::from_be_bytes([setting[0], setting[1]]);
+                    let value = u32::from_be_bytes(setting[2..].try_into().unwrap());
+                    if id == SETTINGS_MAX_CONCURRENT_STREAMS {
+                        // Zero would leave nothing to send, one stream gets refused instead
+                        self.limit = self.streams.min(value as usize).max(1);
+                    }
+                }
+            }
+            PING if header.flags & ACK == 0 => self.ping = payload.try_into().ok(),
+            _ => {}
+        }
+    }
+
+    fn end_headers(&mut self, now: Instant, stats: &mut Statistics) {
+        let mut good = true;
+        let mut grpc_status = None;
+        let decoded = self.decoder.decode(&self.block, |name, value| match name {
+            b":status" if !value.starts_with(b"2") => good = false,
+            b"grpc-status" => grpc_status = Some(grpc::parse_status(value)),
+            _ => {}
+        });
+        if decoded.is_err() {
+            // Header table is out of sync with the server, nothing after this can be read
+            self.reconnect = true;
+            return;
+        }
+
+        let stream = self.block_stream;
+        // Call status comes in trailers, or in the only header block when the call failed early
+        if self.grpc && self.block_ends_stream {
+            match grpc_status {
+                Some(0) => {}
+                Some(status) => {
+                    stats.increment_grpc_status(status);
+                    good = false;
+                }
+                None => good = false,
+            }
+        }
+        if !good {
+            self.fail_stream(stream);
+        }
+        if self.block_ends_stream {
+            self.end_stream(stream, now, stats);
+        }
+    }
+
+    fn fail_stream(&mut self, id: u32) {
+        if let Some(stream) = self.open.iter_mut().find(|x| x.id == id) {
+            stream.failed = true;
+        }
+    }
+
+    /// Count stream in flight as ended, streams we do not know are ignored
+    fn end_stream(&mut self, id: u32, now: Instant, stats: &mut Statistics) {
+        let Some(index) = self.open.iter().position(|x| x.id == id) else {
+            return;
+        };
+        let stream = self.open.swap_remove(index);
+        self.ended += 1;
+
+        if let Some(started) = stream.started {
+            stats.new_measurement(now.saturating_duration_since(started));
+        }
+        if stream.failed {
+            stats.increment_wrong_returns(Failure::Reply);
+        } else {
+            self.good += 1;
+        }
+    }
+}
+
+fn write_frame(
+    out: &mut Cursor<&mut [u8]>,
+    kind: u8,
+    flags: u8,
+    stream: u32,
+    payload: &[u8],
+) -> std::io::Result<()> {
+    out.write_all(&(payload.len() as u32).to_be_bytes()[1..])?;
+    out.write_all(&[kind, flags])?;
+    out.write_all(&stream.to_be_bytes())?;
+    out.write_all(payload)
+}
+
+/// Header of the frame at the start of buf, None while not all of the header is in
+fn parse_header(buf: &[u8]) -> Option<FrameHeader> {
+    let header = buf.get(..FRAME_HEADER)?;
+    Some(FrameHeader {
+        len: u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize,
+        kind: header[3],
+        flags: header[4],
+        stream: u32::from_be_bytes(header[5..9].try_into().unwrap()) & MAX_STREAM,
+    })
+}
+
+/// Header of the frame at the start of buf, None while not all of the frame is in
+fn parse_frame(buf: &[u8]) -> Option<FrameHeader> {
+    parse_header(buf).filter(|header| buf.len() >= FRAME_HEADER + header.len)
+}
+
+/// Header block fragment of a HEADERS frame without its padding and priority
+fn header_fragment(flags: u8, mut payload: &[u8]) -> Option<&[u8]> {
+    if flags & PADDED != 0 {
+        let (&padding, rest) = payload.split_first()?;
+        payload = rest.get(..rest.len().checked_sub(padding as usize)?)?;
+    }
+    if flags & PRIORITY != 0 {
+        payload = payload.get(5..)?;
+    }
+    Some(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    fn frame(kind: u8, flags: u8, stream: u32, payload: &[u8]) -> Vec<u8> {
+        let mut buf = vec![0; FRAME_HEADER + payload.len()];
+        write_frame(&mut Cursor::new(&mut buf[..]), kind, flags, stream, payload).unwrap();
+        buf
+    }
+
+    fn max_streams(value: u32) -> Vec<u8> {
+        let mut payload = SETTINGS_MAX_CONCURRENT_STREAMS.to_be_bytes().to_vec();
+        payload.extend_from_slice(&value.to_be_bytes());
+        frame(SETTINGS, 0, 0, &payload)
+    }
+
+    /// Connection that went through handshake with given server preface
+    fn connected(streams: usize, preface: &[u8]) -> Http2 {
+        let settings = Settings {
+            workload: WorkloadKind::Http2,
+            h2: Http2Options { streams },
+            ..Settings::default()
+        };
+        let mut h2: Http2 = testing::connection(settings);
+        h2.handshake(&mut [0; 4096]);
+        assert_eq!(h2.handshake_frame(preface), Frame::Complete(preface.len()));
+        h2.handshake_done(preface);
+        h2
+    }
+
+    #[test]
+    fn frame_header_waits_for_payload() {
+        let buf = frame(PING, 0, 0, &[7; 8]);
+        for split in 0..buf.len() {
+            assert!(parse_frame(&buf[..split]).is_none());
+        }
+        assert!(parse_header(&buf[..FRAME_HEADER]).is_some());
+        let header = parse_frame(&buf).unwrap();
+        assert_eq!((header.len, header.kind, header.stream), (8, PING, 0));
+    }
+
+    #[test]
+    fn header_fragment_strips_padding_and_priority() {
+        assert_eq!(header_fragment(0, b"abc"), Some(&b"abc"[..]));
+        assert_eq!(header_fragment(PADDED, b"\x02abcxx"), Some(&b"abc"[..]));
+        assert_eq!(header_fragment(PRIORITY, b"12345abc"), Some(&b"abc"[..]));
+        assert_eq!(
+            header_fragment(PADDED | PRIORITY, b"\x0112345abcx"),
+            Some(&b"abc"[..])
+        );
+        // Padding longer than the frame, priority cut short
+        assert_eq!(header_fragment(PADDED, b"\x05abc"), None);
+        assert_eq!(header_fragment(PRIORITY, b"123"), None);
+    }
+
+    #[test]
+    fn handshake_waits_for_whole_frames() {
+        let h2 = connected(1, &frame(SETTINGS, 0, 0, &[]));
+        let mut preface = max_streams(100);
+        preface.extend(frame(WINDOW_UPDATE, 0, 0, &[0, 0, 1, 0]));
+        for split in 0..preface.len() {
+            if split != max_streams(100).len() {
+                assert_eq!(h2.handshake_frame(&preface[..split]), Frame::Incomplete);
+            }
+        }
+        assert_eq!(h2.handshake_frame(&preface), Frame::Complete(preface.len()));
+        // SETTINGS ACK alone is not the server preface
+        assert_eq!(
+            h2.handshake_frame(&frame(SETTINGS, ACK, 0, &[])),
+            Frame::Incomplete
+        );
+        assert_eq!(
+            h2.handshake_frame(&frame(GOAWAY, 0, 0, &[0; 8])),
+            Frame::Invalid
+        );
+    }
+
+    #[test]
+    fn server_settings_are_acked_and_cap_streams() {
+        let mut h2 = connected(8, &max_streams(2));
+        let mut buf = [0; 4096];
+        let len = h2.request(&mut buf);
+
+        let ack = parse_header(&buf[..len]).unwrap();
+        assert_eq!((ack.kind, ack.flags, ack.len), (SETTINGS, ACK, 0));
+        let ids: Vec<u32> = h2.open.iter().map(|x| x.id).collect();
+        assert_eq!(ids, [1, 3]);
+        assert_eq!(len, FRAME_HEADER + 2 * h2.stream_len());
+
+        // Nothing is owed the second time
+        let mut h2 = connected(8, &max_streams(0));
+        assert_eq!(h2.limit, 1);
+        h2.settings_ack = false;
+        assert_eq!(h2.request(&mut buf), h2.stream_len());
+    }
+
+    /// What tasks do with every read: hand it over, drop what is done with and ask for a frame
+    fn exchange(
+        h2: &mut Http2,
+        pending: &mut Vec<u8>,
+        read: &[u8],
+        stats: &mut Statistics,
+    ) -> Frame {
+        pending.extend_from_slice(read);
+        h2.read(pending, Instant::now(), stats);
+        pending.drain(..h2.drain());
+        h2.frame(&[], pending)
+    }
+
+    #[test]
+    fn streams_end_one_by_one() {
+        let mut h2 = connected(2, &frame(SETTINGS, 0, 0, &[]));
+        let mut buf = [0; 4096];
+        h2.request(&mut buf);
+        h2.sent(Instant::now());
+
+        // :status 404 on stream 1, :status 200 on stream 3 whose DATA is split
+        let mut received = frame(HEADERS, END_HEADERS | END_STREAM, 1, &[0x8d]);
+        let first = received.len();
+        received.extend(frame(HEADERS, END_HEADERS, 3, &[0x88]));
+        received.extend(frame(DATA, END_STREAM, 3, b"ok"));
+        let split = received.len() - 1;
+
+        let mut stats = Statistics::default();
+        let mut pending = Vec::new();
+        let read = &received[..first - 1];
+        assert_eq!(
+            exchange(&mut h2, &mut pending, read, &mut stats),
+            Frame::Incomplete
+        );
+        assert_eq!(pending.len(), first - 1);
+
+        let read = &received[first - 1..split];
+        assert_eq!(
+            exchange(&mut h2, &mut pending, read, &mut stats),
+            Frame::Complete(0)
+        );
+        assert_eq!((h2.ended, h2.good), (1, 0));
+        assert_eq!(stats.errors(), 1);
+        assert!(pending.is_empty());
+
+        // Rest of the DATA frame ends the other stream in the next exchange
+        h2.request(&mut buf);
+        let read = &received[split..];
+        assert_eq!(
+            exchange(&mut h2, &mut pending, read, &mut stats),
+            Frame::Complete(0)
+        );
+        assert_eq!(h2.requests_per_exchange(), 1);
+        // First byte went back in the WINDOW_UPDATE of the last request
+        assert_eq!(h2.consumed, 1);
+    }
+
+    #[test]
+    fn data_bigger_than_receive_buffer() {
+        let mut h2 = connected(1, &frame(SETTINGS, 0, 0, &[]));
+        let mut buf = [0; 4096];
+        h2.request(&mut buf);
+
+        let mut received = frame(HEADERS, END_HEADERS, 1, &[0x88]);
+        received.extend(frame(DATA, 0, 1, &[1; 20_000]));
+        received.extend(frame(DATA, END_STREAM, 1, &[2; 16_384]));
+
+        let mut stats = Statistics::default();
+        let mut pending = Vec::new();
+        let reads: Vec<&[u8]> = received.chunks(buf.len()).collect();
+        for (index, read) in reads.iter().enumerate() {
+            let framed = exchange(&mut h2, &mut pending, read, &mut stats);
+            assert!(pending.len() < FRAME_HEADER);
+            match index == reads.len() - 1 {
+                true => assert_eq!(framed, Frame::Complete(0)),
+                false => assert_eq!(framed, Frame::Incomplete),
+            }
+        }
+        assert_eq!(h2.requests_per_exchange(), 1);
+        assert_eq!(h2.consumed, 36_384);
+
+        // Connection window is handed back with the next streams
+        let len = h2.request(&mut buf);
+        let update = parse_frame(&buf[..len]).unwrap();
+        assert_eq!(update.kind, WINDOW_UPDATE);
+        assert_eq!(buf[FRAME_HEADER..FRAME_HEADER + 4], 36_384u32.to_be_bytes());
+    }
+
+    #[test]
+    fn goaway_reconnects() {
+        let mut h2 = connected(1, &frame(SETTINGS, 0, 0, &[]));
+        h2.request(&mut [0; 4096]);
+        let received = frame(GOAWAY, 0, 0, &[0; 8]);
+        h2.read(&received, Instant::now(), &mut Statistics::default());
+        assert_eq!(h2.frame(&[], &received), Frame::Reconnect);
+    }
+}
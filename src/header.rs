@@ -0,0 +1,92 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Bytes a `RequestHeader` takes at the start of a payload
+pub const HEADER_LEN: usize = 4 * size_of::<u64>();
+
+/// Header at the start of every request when running with `--header yes`
+///
+/// On the wire it is four little endian u64 right after each other:
+///
+/// | offset | field          |
+/// |--------|----------------|
+/// | 0      | `sequence`     |
+/// | 8      | `connection`   |
+/// | 16     | `sent_nanos`   |
+/// | 24     | `server_nanos` |
+///
+/// Plain echo servers need to do nothing. Servers that want to report their side of the
+/// latency write nanoseconds spent on the request into `server_nanos` of the response and
+/// leave the rest as it was, zero there means nothing was reported.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RequestHeader {
+    /// Grows by one with every request of a connection, UDP retransmissions included
+    pub sequence: u64,
+    /// Unique across all threads of one run
+    pub connection: u64,
+    /// Wall clock time the request was built at, nanoseconds since UNIX epoch
+    pub sent_nanos: u64,
+    /// Filled by cooperating servers
+    pub server_nanos: u64,
+}
+
+impl RequestHeader {
+    pub fn new(sequence: u64, connection: u64) -> Self {
+        let sent_nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_nanos() as u64)
+            .unwrap_or(0);
+        Self {
+            sequence,
+            connection,
+            sent_nanos,
+            server_nanos: 0,
+        }
+    }
+
+    /// Write header into first `HEADER_LEN` bytes of buf
+    pub fn write(&self, buf: &mut [u8]) {
+        let fields = [
+            self.sequence,
+            self.connection,
+            self.sent_nanos,
+            self.server_nanos,
+        ];
+        for (chunk, field) in buf[..HEADER_LEN].chunks_exact_mut(8).zip(fields) {
+            chunk.copy_from_slice(&field.to_le_bytes());
+        }
+    }
+
+    /// Read header from first `HEADER_LEN` bytes of buf
+    pub fn read(buf: &[u8]) -> Self {
+        let mut fields = buf[..HEADER_LEN]
+            .chunks_exact(8)
+            .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()));
+        let mut next = || fields.next().unwrap();
+        Self {
+            sequence: next(),
+            connection: next(),
+            sent_nanos: next(),
+            server_nanos: next(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let header = RequestHeader {
+            server_nanos: 1234,
+            ..RequestHeader::new(u64::MAX, 7)
+        };
+        let mut buf = [0xff; HEADER_LEN + 4];
+        header.write(&mut buf);
+        assert_eq!(RequestHeader::read(&buf), header);
+        // Little endian fields one after another, bytes past the header left alone
+        assert_eq!(buf[8..16], 7u64.to_le_bytes());
+        assert_eq!(buf[24..32], 1234u64.to_le_bytes());
+        assert_eq!(buf[HEADER_LEN..], [0xff; 4]);
+    }
+}
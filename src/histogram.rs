@@ -0,0 +1,158 @@
+use std::fmt;
+
+/// Bits of precision kept below the leading one, 4 bits gives under 6.25% error
+const SUB_BITS: u32 = 4;
+const SUB_BUCKETS: usize = 1 << SUB_BITS;
+const BUCKETS: usize = (64 - SUB_BITS as usize + 1) * SUB_BUCKETS;
+
+/// Log-linear latency histogram, cheap enough to record every response into
+///
+/// Values below 16 are exact, above that every power of two is split into 16 equal buckets
+pub struct Histogram {
+    counts: Box<[u64]>,
+    total: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            counts: vec![0; BUCKETS].into_boxed_slice(),
+            total: 0,
+        }
+    }
+}
+
+impl fmt::Debug for Histogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Histogram")
+            .field("total", &self.total)
+            .finish()
+    }
+}
+
+impl Histogram {
+    #[inline]
+    pub fn record(&mut self, value: u64) {
+        self.counts[index_of(value)] += 1;
+        self.total += 1;
+    }
+
+    pub fn merge(&mut self, other: &Histogram) {
+        for (ours, theirs) in self.counts.iter_mut().zip(other.counts.iter()) {
+            *ours += theirs;
+        }
+        self.total += other.total;
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Highest value of the bucket the quantile falls into, quantile being in range 0..=1
+    pub fn value_at_quantile(&self, quantile: f64) -> u64 {
+        if self.total == 0 {
+            return 0;
+        }
+
+        let target = ((quantile * self.total as f64).ceil() as u64).clamp(1, self.total);
+        let mut seen = 0;
+        for (index, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= target {
+                return highest_of(index);
+            }
+        }
+
+        u64::MAX
+    }
+}
+
+#[inline]
+fn index_of(value: u64) -> usize {
+    if value < SUB_BUCKETS as u64 {
+        return value as usize;
+    }
+
+    let shift = 63 - value.leading_zeros() - SUB_BITS;
+    let sub = (value >> shift) as usize & (SUB_BUCKETS - 1);
+    (shift as usize + 1) * SUB_BUCKETS + sub
+}
+
+fn highest_of(index: usize) -> u64 {
+    if index < SUB_BUCKETS {
+        return index as u64;
+    }
+
+    let shift = (index / SUB_BUCKETS - 1) as u32;
+    let sub = (index % SUB_BUCKETS) as u64;
+    let lowest = (SUB_BUCKETS as u64 + sub) << shift;
+    lowest + ((1u64 << shift) - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty() {
+        let histogram = Histogram::default();
+        for quantile in [0.0, 0.5, 1.0] {
+            assert_eq!(histogram.value_at_quantile(quantile), 0);
+        }
+    }
+
+    #[test]
+    fn single_value() {
+        let mut histogram = Histogram::default();
+        histogram.record(1000);
+        let bucket = highest_of(index_of(1000));
+        assert!((1000..1000 + 1000 / SUB_BUCKETS as u64).contains(&bucket));
+        for quantile in [-1.0, 0.0, 0.5, 1.0, 2.0] {
+            assert_eq!(histogram.value_at_quantile(quantile), bucket);
+        }
+    }
+
+    #[test]
+    fn quantile_edges() {
+        let mut histogram = Histogram::default();
+        for value in 1..=10 {
+            histogram.record(value);
+        }
+        // Small values are exact, 0 is the lowest and 1 the highest
+        assert_eq!(histogram.value_at_quantile(0.0), 1);
+        assert_eq!(histogram.value_at_quantile(0.1), 1);
+        assert_eq!(histogram.value_at_quantile(0.11), 2);
+        assert_eq!(histogram.value_at_quantile(0.5), 5);
+        assert_eq!(histogram.value_at_quantile(1.0), 10);
+
+        histogram.record(u64::MAX);
+        assert_eq!(histogram.value_at_quantile(1.0), u64::MAX);
+    }
+
+    #[test]
+    fn buckets() {
+        let mut values: Vec<u64> = (0..1 << 12).collect();
+        values.extend((12..64).flat_map(|shift| [(1 << shift) - 1, 1 << shift, (1 << shift) + 1]));
+        values.push(u64::MAX);
+        for value in values {
+            let index = index_of(value);
+            let highest = highest_of(index);
+            assert!(index < BUCKETS);
+            assert!(highest >= value);
+            assert_eq!(index_of(highest), index);
+            assert!(highest - value <= value >> SUB_BITS);
+        }
+    }
+
+    #[test]
+    fn merge() {
+        let mut ours = Histogram::default();
+        let mut theirs = Histogram::default();
+        ours.record(1);
+        theirs.record(3);
+        theirs.record(3);
+        ours.merge(&theirs);
+        assert_eq!(ours.total(), 3);
+        assert_eq!(ours.value_at_quantile(0.34), 3);
+    }
+}
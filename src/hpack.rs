@@ -0,0 +1,555 @@
+//! Just enough HPACK (RFC 7541) for HTTP/2 workloads
+//!
+//! Requests are encoded without indexing or Huffman so encoding needs no state, responses are
+//! decoded in full since servers may use the dynamic table and Huffman as they please.
+
+use std::{borrow::Cow, collections::VecDeque, sync::OnceLock};
+
+/// Dynamic table size both sides start with, we never ask for another one
+const DEFAULT_TABLE_SIZE: usize = 4096;
+/// Per entry overhead counted towards table size
+const ENTRY_OVERHEAD: usize = 32;
+const EOS: u16 = 256;
+
+/// Indexed header field, index into static table (or dynamic one above 61)
+pub fn indexed(out: &mut Vec<u8>, index: usize) {
+    encode_int(out, 0x80, 7, index);
+}
+
+/// Literal without indexing whose name is taken from given table index
+pub fn literal(out: &mut Vec<u8>, name_index: usize, value: &[u8]) {
+    encode_int(out, 0x00, 4, name_index);
+    encode_string(out, value);
+}
+
+/// Literal without indexing with a name of its own
+pub fn literal_new_name(out: &mut Vec<u8>, name: &[u8], value: &[u8]) {
+    out.push(0x00);
+    encode_string(out, name);
+    encode_string(out, value);
+}
+
+fn encode_string(out: &mut Vec<u8>, value: &[u8]) {
+    encode_int(out, 0x00, 7, value.len());
+    out.extend_from_slice(value);
+}
+
+fn encode_int(out: &mut Vec<u8>, flags: u8, prefix: u32, mut value: usize) {
+    let max = (1 << prefix) - 1;
+    if value < max {
+        out.push(flags | value as u8);
+        return;
+    }
+
+    out.push(flags | max as u8);
+    value -= max;
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// Header block that does not decode
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Malformed;
+
+/// Header block decoder of one connection, blocks have to go through it in the order they came
+#[derive(Clone, Debug)]
+pub struct Decoder {
+    /// Newest entry first
+    dynamic: VecDeque<(Vec<u8>, Vec<u8>)>,
+    size: usize,
+    max_size: usize,
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self {
+            dynamic: VecDeque::new(),
+            size: 0,
+            max_size: DEFAULT_TABLE_SIZE,
+        }
+    }
+}
+
+impl Decoder {
+    /// Decode a whole header block, header is called with every field in it
+    ///
+    /// Error means the block is broken and so is the connection, its table can not be trusted
+    pub fn decode(
+        &mut self,
+        block: &[u8],
+        header: impl FnMut(&[u8], &[u8]),
+    ) -> Result<(), Malformed> {
+        self.decode_block(block, header).map_err(|()| Malformed)
+    }
+
+    fn decode_block(
+        &mut self,
+        mut block: &[u8],
+        mut header: impl FnMut(&[u8], &[u8]),
+    ) -> Result<(), ()> {
+        while let Some(&first) = block.first() {
+            if first & 0x80 != 0 {
+                let index = decode_int(&mut block, 7)?;
+                let (name, value) = self.get(index)?;
+                header(name, value);
+            } else if first & 0x40 != 0 {
+                // Literal with incremental indexing
+                let index = decode_int(&mut block, 6)?;
+                let name = self.name(index, &mut block)?;
+                let value = decode_string(&mut block)?;
+                header(&name, &value);
+                self.insert(name.into_owned(), value.into_owned());
+            } else if first & 0x20 != 0 {
+                let size = decode_int(&mut block, 5)?;
+                if size > DEFAULT_TABLE_SIZE {
+                    return Err(());
+                }
+                self.max_size = size;
+                self.evict(0);
+            } else {
+                // Literal without indexing or never indexed, same thing for a decoder
+                let index = decode_int(&mut block, 4)?;
+                let name = self.name(index, &mut block)?;
+                let value = decode_string(&mut block)?;
+                header(&name, &value);
+            }
+        }
+        Ok(())
+    }
+
+    fn get(&self, index: usize) -> Result<(&[u8], &[u8]), ()> {
+        match index {
+            0 => Err(()),
+            1..=61 => Ok(STATIC_TABLE[index - 1]),
+            _ => self
+                .dynamic
+                .get(index - 62)
+                .map(|(name, value)| (name.as_slice(), value.as_slice()))
+                .ok_or(()),
+        }
+    }
+
+    /// Name of a literal, zero index means it follows as a string
+    fn name<'a>(&self, index: usize, block: &mut &'a [u8]) -> Result<Cow<'a, [u8]>, ()> {
+        match index {
+            0 => decode_string(block),
+            1..=61 => Ok(Cow::Borrowed(STATIC_TABLE[index - 1].0)),
+            // Copied out as the entry may get evicted by the very literal it names
+            _ => self.get(index).map(|(name, _)| Cow::Owned(name.to_vec())),
+        }
+    }
+
+    fn insert(&mut self, name: Vec<u8>, value: Vec<u8>) {
+        let size = name.len() + value.len() + ENTRY_OVERHEAD;
+        self.evict(size);
+        // Entry bigger than the whole table just empties it
+        if size <= self.max_size {
+            self.size += size;
+            self.dynamic.push_front((name, value));
+        }
+    }
+
+    /// Drop oldest entries until there is room for given size
+    fn evict(&mut self, room: usize) {
+        while self.size + room > self.max_size {
+            let Some((name, value)) = self.dynamic.pop_back() else {
+                break;
+            };
+            self.size -= name.len() + value.len() + ENTRY_OVERHEAD;
+        }
+    }
+}
+
+fn decode_int(block: &mut &[u8], prefix: u32) -> Result<usize, ()> {
+    let (&first, rest) = block.split_first().ok_or(())?;
+    *block = rest;
+
+    let max = (1 << prefix) - 1;
+    let mut value = first as usize & max;
+    if value < max {
+        return Ok(value);
+    }
+
+    let mut shift = 0;
+    loop {
+        let (&byte, rest) = block.split_first().ok_or(())?;
+        *block = rest;
+        value += ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+        shift += 7;
+        if shift > 28 {
+            return Err(());
+        }
+    }
+}
+
+fn decode_string<'a>(block: &mut &'a [u8]) -> Result<Cow<'a, [u8]>, ()> {
+    let huffman = block.first().ok_or(())? & 0x80 != 0;
+    let len = decode_int(block, 7)?;
+    if block.len() < len {
+        return Err(());
+    }
+    let (string, rest) = block.split_at(len);
+    *block = rest;
+
+    if huffman {
+        decode_huffman(string).map(Cow::Owned)
+    } else {
+        Ok(Cow::Borrowed(string))
+    }
+}
+
+/// Huffman code is canonical, codes of one length follow each other in symbol order
+struct Huffman {
+    /// First code of every length
+    first: [u32; 31],
+    /// Codes of every length
+    count: [u32; 31],
+    /// Where symbols of every length start in `symbols`
+    offset: [usize; 31],
+    /// Symbols ordered by code
+    symbols: Vec<u16>,
+}
+
+fn huffman() -> &'static Huffman {
+    static HUFFMAN: OnceLock<Huffman> = OnceLock::new();
+    HUFFMAN.get_or_init(|| {
+        let mut symbols: Vec<u16> = (0..=EOS).collect();
+        symbols.sort_by_key(|&symbol| HUFFMAN_LENGTHS[symbol as usize]);
+
+        let mut count = [0; 31];
+        for &len in HUFFMAN_LENGTHS.iter() {
+            count[len as usize] += 1;
+        }
+
+        let mut first = [0; 31];
+        let mut offset = [0; 31];
+        let mut code = 0;
+        for len in 1..31 {
+            code = (code + count[len - 1]) << 1;
+            first[len] = code;
+            offset[len] = offset[len - 1] + count[len - 1] as usize;
+        }
+
+        Huffman {
+            first,
+            count,
+            offset,
+            symbols,
+        }
+    })
+}
+
+fn decode_huffman(string: &[u8]) -> Result<Vec<u8>, ()> {
+    let huffman = huffman();
+    let mut out = Vec::with_capacity(string.len() * 8 / 5);
+    let mut code = 0u32;
+    let mut len = 0;
+
+    for byte in string {
+        for bit in (0..8).rev() {
+            code = code << 1 | (byte >> bit) as u32 & 1;
+            len += 1;
+
+            let index = code.wrapping_sub(huffman.first[len]);
+            if index < huffman.count[len] {
+                let symbol = huffman.symbols[huffman.offset[len] + index as usize];
+                if symbol == EOS {
+                    return Err(());
+                }
+                out.push(symbol as u8);
+                code = 0;
+                len = 0;
+            } else if len == 30 {
+                return Err(());
+            }
+        }
+    }
+
+    // Padding is the start of EOS, all ones and shorter than a byte
+    if len > 7 || code != (1 << len) - 1 {
+        return Err(());
+    }
+    Ok(out)
+}
+
+/// Huffman code length of every symbol, EOS last (RFC 7541 appendix B)
+const HUFFMAN_LENGTHS: [u8; 257] = [
+    13, 23, 28, 28, 28, 28, 28, 28, 28, 24, 30, 28, 28, 30, 28, 28, 28, 28, 28, 28, 28, 28, 30, 28,
+    28, 28, 28, 28, 28, 28, 28, 28, 6, 10, 10, 12, 13, 6, 8, 11, 10, 10, 8, 11, 8, 6, 6, 6, 5, 5,
+    5, 6, 6, 6, 6, 6, 6, 6, 7, 8, 15, 6, 12, 10, 13, 6, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7,
+    7, 7, 7, 7, 7, 7, 7, 7, 8, 7, 8, 13, 19, 13, 14, 6, 15, 5, 6, 5, 6, 5, 6, 6, 6, 5, 7, 7, 6, 6,
+    6, 5, 6, 7, 6, 5, 5, 6, 7, 7, 7, 7, 7, 15, 11, 14, 13, 28, 20, 22, 20, 20, 22, 22, 22, 23, 22,
+    23, 23, 23, 23, 23, 24, 23, 24, 24, 22, 23, 24, 23, 23, 23, 23, 21, 22, 23, 22, 23, 23, 24, 22,
+    21, 20, 22, 22, 23, 23, 21, 23, 22, 22, 24, 21, 22, 23, 23, 21, 21, 22, 21, 23, 22, 23, 23, 20,
+    22, 22, 22, 23, 22, 22, 23, 26, 26, 20, 19, 22, 23, 22, 25, 26, 26, 26, 27, 27, 26, 24, 25, 19,
+    21, 26, 27, 27, 26, 27, 24, 21, 21, 26, 26, 28, 27, 27, 27, 20, 24, 20, 21, 22, 21, 21, 23, 22,
+    22, 25, 25, 24, 24, 26, 23, 26, 27, 26, 26, 27, 27, 27, 27, 27, 28, 27, 27, 27, 27, 27, 26, 30,
+];
+
+/// Static table of RFC 7541 appendix A, index 1 is first
+const STATIC_TABLE: [(&[u8], &[u8]); 61] = [
+    (b":authority", b""),
+    (b":method", b"GET"),
+    (b":method", b"POST"),
+    (b":path", b"/"),
+    (b":path", b"/index.html"),
+    (b":scheme", b"http"),
+    (b":scheme", b"https"),
+    (b":status", b"200"),
+    (b":status", b"204"),
+    (b":status", b"206"),
+    (b":status", b"304"),
+    (b":status", b"400"),
+    (b":status", b"404"),
+    (b":status", b"500"),
+    (b"accept-charset", b""),
+    (b"accept-encoding", b"gzip, deflate"),
+    (b"accept-language", b""),
+    (b"accept-ranges", b""),
+    (b"accept", b""),
+    (b"access-control-allow-origin", b""),
+    (b"age", b""),
+    (b"allow", b""),
+    (b"authorization", b""),
+    (b"cache-control", b""),
+    (b"content-disposition", b""),
+    (b"content-encoding", b""),
+    (b"content-language", b""),
+    (b"content-length", b""),
+    (b"content-location", b""),
+    (b"content-range", b""),
+    (b"content-type", b""),
+    (b"cookie", b""),
+    (b"date", b""),
+    (b"etag", b""),
+    (b"expect", b""),
+    (b"expires", b""),
+    (b"from", b""),
+    (b"host", b""),
+    (b"if-match", b""),
+    (b"if-modified-since", b""),
+    (b"if-none-match", b""),
+    (b"if-range", b""),
+    (b"if-unmodified-since", b""),
+    (b"last-modified", b""),
+    (b"link", b""),
+    (b"location", b""),
+    (b"max-forwards", b""),
+    (b"proxy-authenticate", b""),
+    (b"proxy-authorization", b""),
+    (b"range", b""),
+    (b"referer", b""),
+    (b"refresh", b""),
+    (b"retry-after", b""),
+    (b"server", b""),
+    (b"set-cookie", b""),
+    (b"strict-transport-security", b""),
+    (b"transfer-encoding", b""),
+    (b"user-agent", b""),
+    (b"vary", b""),
+    (b"via", b""),
+    (b"www-authenticate", b""),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(value: &str) -> Vec<u8> {
+        let digits: Vec<u8> = value.bytes().filter(|x| !x.is_ascii_whitespace()).collect();
+        digits
+            .chunks(2)
+            .map(|x| u8::from_str_radix(std::str::from_utf8(x).unwrap(), 16).unwrap())
+            .collect()
+    }
+
+    fn decode(decoder: &mut Decoder, block: &str) -> Vec<(String, String)> {
+        let mut headers = Vec::new();
+        decoder
+            .decode(&hex(block), |name, value| {
+                headers.push((
+                    String::from_utf8(name.to_vec()).unwrap(),
+                    String::from_utf8(value.to_vec()).unwrap(),
+                ))
+            })
+            .unwrap();
+        headers
+    }
+
+    fn fields(fields: &[(&str, &str)]) -> Vec<(String, String)> {
+        fields
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    /// RFC 7541 C.1
+    #[test]
+    fn integers() {
+        let mut out = Vec::new();
+        encode_int(&mut out, 0, 5, 10);
+        encode_int(&mut out, 0, 5, 1337);
+        encode_int(&mut out, 0, 8, 42);
+        assert_eq!(out, [0x0a, 0x1f, 0x9a, 0x0a, 0x2a]);
+
+        let mut block = &out[1..];
+        assert_eq!(decode_int(&mut block, 5), Ok(1337));
+        assert_eq!(block, [0x2a]);
+        assert_eq!(decode_int(&mut &[0x1f, 0x9a][..], 5), Err(()));
+        assert_eq!(decode_int(&mut &[0xff; 8][..], 8), Err(()));
+    }
+
+    /// RFC 7541 C.2.2 and C.2.4
+    #[test]
+    fn encoder() {
+        let mut out = Vec::new();
+        literal(&mut out, 4, b"/sample/path");
+        assert_eq!(out, hex("040c 2f73 616d 706c 652f 7061 7468"));
+
+        out.clear();
+        indexed(&mut out, 2);
+        assert_eq!(out, [0x82]);
+
+        out.clear();
+        literal_new_name(&mut out, b"custom-key", b"custom-header");
+        indexed(&mut out, 62);
+        let mut decoder = Decoder::default();
+        decoder.insert(b"custom-key".to_vec(), b"custom-header".to_vec());
+        let mut headers = Vec::new();
+        decoder
+            .decode(&out, |name, value| {
+                headers.push((name.to_vec(), value.to_vec()))
+            })
+            .unwrap();
+        assert_eq!(headers[0], headers[1]);
+    }
+
+    /// RFC 7541 C.4, requests with Huffman
+    #[test]
+    fn huffman_requests() {
+        let mut decoder = Decoder::default();
+        assert_eq!(
+            decode(&mut decoder, "8286 8441 8cf1 e3c2 e5f2 3a6b a0ab 90f4 ff"),
+            fields(&[
+                (":method", "GET"),
+                (":scheme", "http"),
+                (":path", "/"),
+                (":authority", "www.example.com"),
+            ])
+        );
+        assert_eq!(decoder.size, 57);
+
+        assert_eq!(
+            decode(&mut decoder, "8286 84be 5886 a8eb 1064 9cbf"),
+            fields(&[
+                (":method", "GET"),
+                (":scheme", "http"),
+                (":path", "/"),
+                (":authority", "www.example.com"),
+                ("cache-control", "no-cache"),
+            ])
+        );
+        assert_eq!(decoder.size, 110);
+
+        assert_eq!(
+            decode(
+                &mut decoder,
+                "8287 85bf 4088 25a8 49e9 5ba9 7d7f 8925 a849 e95b b8e8 b4bf"
+            ),
+            fields(&[
+                (":method", "GET"),
+                (":scheme", "https"),
+                (":path", "/index.html"),
+                (":authority", "www.example.com"),
+                ("custom-key", "custom-value"),
+            ])
+        );
+        assert_eq!(decoder.size, 164);
+        assert_eq!(decoder.dynamic.len(), 3);
+    }
+
+    /// RFC 7541 C.6, responses with Huffman and a 256 byte table that evicts
+    #[test]
+    fn huffman_responses() {
+        let mut decoder = Decoder {
+            max_size: 256,
+            ..Decoder::default()
+        };
+        assert_eq!(
+            decode(
+                &mut decoder,
+                "4882 6402 5885 aec3 771a 4b61 96d0 7abe 9410 54d4 44a8 2005 9504 0b81 66e0 82a6
+                 2d1b ff6e 919d 29ad 1718 63c7 8f0b 97c8 e9ae 82ae 43d3"
+            ),
+            fields(&[
+                (":status", "302"),
+                ("cache-control", "private"),
+                ("date", "Mon, 21 Oct 2013 20:13:21 GMT"),
+                ("location", "https://www.example.com"),
+            ])
+        );
+        assert_eq!(decoder.size, 222);
+
+        assert_eq!(
+            decode(&mut decoder, "4883 640e ffc1 c0bf"),
+            fields(&[
+                (":status", "307"),
+                ("cache-control", "private"),
+                ("date", "Mon, 21 Oct 2013 20:13:21 GMT"),
+                ("location", "https://www.example.com"),
+            ])
+        );
+        assert_eq!(decoder.size, 222);
+
+        assert_eq!(
+            decode(
+                &mut decoder,
+                "88c1 6196 d07a be94 1054 d444 a820 0595 040b 8166 e084 a62d 1bff c05a 839b d9ab
+                 77ad 94e7 821d d7f2 e6c7 b335 dfdf cd5b 3960 d5af 2708 7f36 72c1 ab27 0fb5 291f
+                 9587 3160 65c0 03ed 4ee5 b106 3d50 07"
+            ),
+            fields(&[
+                (":status", "200"),
+                ("cache-control", "private"),
+                ("date", "Mon, 21 Oct 2013 20:13:22 GMT"),
+                ("location", "https://www.example.com"),
+                ("content-encoding", "gzip"),
+                (
+                    "set-cookie",
+                    "foo=ASDJKHQKBZXOQWEOPIUAXQWEOIU; max-age=3600; version=1"
+                ),
+            ])
+        );
+        assert_eq!(decoder.size, 215);
+        assert_eq!(decoder.dynamic.len(), 3);
+    }
+
+    #[test]
+    fn malformed() {
+        let mut decoder = Decoder::default();
+        // Index zero, index past dynamic table, string longer than block
+        for block in ["80", "be", "0003 6162"] {
+            assert_eq!(decoder.decode(&hex(block), |_, _| ()), Err(Malformed));
+        }
+        // Table size above the one settled on
+        assert_eq!(decoder.decode(&hex("3fe2 1f"), |_, _| ()), Err(Malformed));
+    }
+
+    #[test]
+    fn huffman_padding() {
+        // "a" is 00011, padded with ones
+        assert_eq!(decode_huffman(&[0x1f]), Ok(b"a".to_vec()));
+        // Padding of zeros
+        assert_eq!(decode_huffman(&[0x18]), Err(()));
+        // A whole byte of padding
+        assert_eq!(decode_huffman(&[0x1f, 0xff]), Err(()));
+        // EOS itself
+        assert_eq!(decode_huffman(&[0xff, 0xff, 0xff, 0xff]), Err(()));
+    }
+}
@@ -0,0 +1,227 @@
+use crate::settings::get_settings;
+
+/// How key/value workloads pick keys out of the key space
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum KeyDistribution {
+    #[default]
+    Uniform,
+    /// Every connection walks the key space one by one, starting at its own offset
+    Sequential,
+    /// Few hot keys get most of the traffic, skew is set by `Settings::zipf_theta`
+    Zipf,
+}
+
+impl KeyDistribution {
+    pub fn parse(value: &str) -> Option<Self> {
+        Some(match value {
+            "uniform" => Self::Uniform,
+            "sequential" => Self::Sequential,
+            "zipf" => Self::Zipf,
+            _ => return None,
+        })
+    }
+}
+
+/// Deterministic key source of one connection, same connection id gives same keys every run
+#[derive(Clone, Debug, Default)]
+pub struct KeyGenerator {
+    distribution: KeyDistribution,
+    keys: u64,
+    rng: SplitMix,
+    /// Next key of sequential walk
+    next: u64,
+    zipf: Option<Zipf>,
+}
+
+impl KeyGenerator {
+    pub fn new(connection: u64) -> Self {
+        let settings = get_settings();
+        let keys = settings.keys.max(1);
+        let distribution = settings.key_distribution;
+        Self {
+            distribution,
+            keys,
+            rng: SplitMix(connection),
+            next: connection % keys,
+            zipf: (distribution == KeyDistribution::Zipf)
+                .then(|| Zipf::new(keys, settings.zipf_theta)),
+        }
+    }
+
+    /// Next key, in `0..keys` with 0 being the hottest one under zipf
+    pub fn next_key(&mut self) -> u64 {
+        match self.distribution {
+            KeyDistribution::Uniform => self.rng.next_u64() % self.keys,
+            KeyDistribution::Sequential => {
+                let key = self.next;
+                self.next = (self.next + 1) % self.keys;
+                key
+            }
+            KeyDistribution::Zipf => {
+                let zipf = self.zipf.as_ref().expect("Zipf is not set up");
+                zipf.sample(&mut self.rng) - 1
+            }
+        }
+    }
+
+    /// Uniform draw from `[0, 1)`
+    pub fn next_f64(&mut self) -> f64 {
+        self.rng.next_f64()
+    }
+}
+
+/// Splitmix64, small and good enough to pick keys
+#[derive(Clone, Debug, Default)]
+pub struct SplitMix(pub u64);
+
+impl SplitMix {
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+}
+
+/// Rejection inversion sampling of Zipf distribution (Hörmann and Derflinger)
+///
+/// Setup is constant time, so large key spaces cost nothing up front unlike the usual
+/// approach that sums the harmonic series
+#[derive(Clone, Debug)]
+struct Zipf {
+    keys: f64,
+    exponent: f64,
+    h_integral_x1: f64,
+    h_integral_n: f64,
+    s: f64,
+}
+
+impl Zipf {
+    fn new(keys: u64, exponent: f64) -> Self {
+        assert!(exponent > 0.0, "Zipf theta has to be above zero");
+        let mut zipf = Self {
+            keys: keys as f64,
+            exponent,
+            h_integral_x1: 0.0,
+            h_integral_n: 0.0,
+            s: 0.0,
+        };
+        zipf.h_integral_x1 = zipf.h_integral(1.5) - 1.0;
+        zipf.h_integral_n = zipf.h_integral(zipf.keys + 0.5);
+        zipf.s = 2.0 - zipf.h_integral_inverse(zipf.h_integral(2.5) - zipf.h(2.0));
+        zipf
+    }
+
+    /// Rank in `1..=keys`
+    fn sample(&self, rand: &mut SplitMix) -> u64 {
+        loop {
+            let u = self.h_integral_n + rand.next_f64() * (self.h_integral_x1 - self.h_integral_n);
+            let x = self.h_integral_inverse(u);
+            let k = (x + 0.5).floor().clamp(1.0, self.keys);
+            if k - x <= self.s || u >= self.h_integral(k + 0.5) - self.h(k) {
+                return k as u64;
+            }
+        }
+    }
+
+    fn h(&self, x: f64) -> f64 {
+        (-self.exponent * x.ln()).exp()
+    }
+
+    fn h_integral(&self, x: f64) -> f64 {
+        let log_x = x.ln();
+        helper2((1.0 - self.exponent) * log_x) * log_x
+    }
+
+    fn h_integral_inverse(&self, x: f64) -> f64 {
+        let t = (x * (1.0 - self.exponent)).max(-1.0);
+        (helper1(t) * x).exp()
+    }
+}
+
+/// `ln(1 + x) / x`, stable around zero
+fn helper1(x: f64) -> f64 {
+    if x.abs() > 1e-8 {
+        x.ln_1p() / x
+    } else {
+        1.0 - x * (0.5 - x * (1.0 / 3.0 - 0.25 * x))
+    }
+}
+
+/// `(exp(x) - 1) / x`, stable around zero
+fn helper2(x: f64) -> f64 {
+    if x.abs() > 1e-8 {
+        x.exp_m1() / x
+    } else {
+        1.0 + x * 0.5 * (1.0 + x / 3.0 * (1.0 + 0.25 * x))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zipf_stays_in_range() {
+        let mut rand = SplitMix(1);
+        for (keys, exponent) in [(1, 0.99), (2, 0.5), (1000, 1.0), (10_000_000_000, 1.2)] {
+            let zipf = Zipf::new(keys, exponent);
+            for _ in 0..10_000 {
+                assert!((1..=keys).contains(&zipf.sample(&mut rand)));
+            }
+        }
+    }
+
+    #[test]
+    fn zipf_follows_distribution() {
+        const KEYS: u64 = 1000;
+        const SAMPLES: usize = 200_000;
+        let exponent = 0.99;
+
+        let zipf = Zipf::new(KEYS, exponent);
+        let mut rand = SplitMix(7);
+        let mut counts = vec![0; KEYS as usize + 1];
+        for _ in 0..SAMPLES {
+            counts[zipf.sample(&mut rand) as usize] += 1;
+        }
+
+        let sum: f64 = (1..=KEYS).map(|k| (k as f64).powf(-exponent)).sum();
+        for rank in [1, 2, 10, 100] {
+            let expected = (rank as f64).powf(-exponent) / sum * SAMPLES as f64;
+            let error = (counts[rank] as f64 - expected).abs() / expected;
+            assert!(error < 0.1, "rank {rank}: {} vs {expected}", counts[rank]);
+        }
+    }
+
+    #[test]
+    fn sequential_walk_wraps() {
+        let mut keys = KeyGenerator {
+            distribution: KeyDistribution::Sequential,
+            keys: 3,
+            next: 2,
+            ..KeyGenerator::default()
+        };
+        let walk: Vec<u64> = (0..4).map(|_| keys.next_key()).collect();
+        assert_eq!(walk, [2, 0, 1, 2]);
+    }
+
+    #[test]
+    fn same_connection_same_keys() {
+        let keys = |connection| {
+            let mut keys = KeyGenerator {
+                keys: 10,
+                rng: SplitMix(connection),
+                ..KeyGenerator::default()
+            };
+            (0..100).map(|_| keys.next_key()).collect::<Vec<_>>()
+        };
+        assert_eq!(keys(1), keys(1));
+        assert_ne!(keys(1), keys(2));
+        assert!(keys(3).iter().all(|&key| key < 10));
+    }
+}
@@ -0,0 +1,36 @@
+//! Benchmarker that does not drag your framework down
+//!
+//! Command line tool is a thin wrapper around `Benchmark`, use it to drive Ultimatum from
+//! your own code or tests
+
+pub mod benchmark;
+pub mod framed;
+pub mod grpc;
+pub mod h2;
+pub mod header;
+pub mod histogram;
+pub mod hpack;
+pub mod keys;
+pub mod memcached;
+pub mod redis;
+pub mod search;
+pub mod sequence;
+pub mod settings;
+pub mod sockopt;
+pub mod stats;
+pub mod stop;
+pub mod tasks;
+#[cfg(test)]
+mod testing;
+pub mod timestamps;
+pub mod tls;
+pub mod uring;
+pub mod validate;
+pub mod worker;
+pub mod workload;
+pub mod ws;
+
+pub use benchmark::Benchmark;
+pub use settings::{Protocol, Settings};
+pub use stats::Statistics;
+pub use workload::{Echo, Frame, Workload};
@@ -1,14 +1,25 @@
-pub mod settings;
-pub mod stats;
-pub mod tasks;
-pub mod uring;
-pub mod worker;
+use ultimatum::{settings, stats, stop, worker, Benchmark};
 
 fn main() {
-    let parsed = settings::load();
+    let parsed = settings::from_args();
     if parsed.debug {
         println!("{:#?}", parsed)
     }
 
-    worker::burn();
+    stop::install();
+
+    if parsed.precise {
+        println!(
+            "Precise timestamps cost about {}ns per completion",
+            worker::clock_cost().as_nanos()
+        );
+    }
+
+    let benchmark = Benchmark::from_settings(parsed);
+    if benchmark.settings().search.is_some() {
+        benchmark.search();
+    } else {
+        let our_stats = benchmark.run();
+        stats::print_stats_final(&our_stats, benchmark.settings());
+    }
 }
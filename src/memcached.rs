@@ -0,0 +1,397 @@
+use std::io::{Cursor, Write};
+
+use crate::{
+    keys::KeyGenerator,
+    settings::{get_settings, Settings},
+    stats::Statistics,
+    validate::Failure,
+    workload::{write_key, Frame, Workload, KEY_LEN},
+};
+
+/// Magic bytes and opcodes of the binary protocol
+const REQUEST_MAGIC: u8 = 0x80;
+const RESPONSE_MAGIC: u8 = 0x81;
+const OP_GET: u8 = 0x00;
+const OP_SET: u8 = 0x01;
+const BINARY_HEADER: usize = 24;
+/// Flags and expiry in front of the key of a binary set, both zero
+const SET_EXTRAS: usize = 8;
+const STATUS_KEY_NOT_FOUND: u16 = 0x0001;
+
+#[derive(Clone, Debug)]
+pub struct MemcachedOptions {
+    /// Binary protocol instead of the text one
+    pub binary: bool,
+    /// Share of requests that are get, rest are set
+    pub get_ratio: f64,
+}
+
+impl Default for MemcachedOptions {
+    fn default() -> Self {
+        Self {
+            binary: false,
+            get_ratio: 0.9,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum Op {
+    #[default]
+    Get,
+    Set,
+}
+
+/// What a memcached response said
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Reply {
+    Hit,
+    Miss,
+    Stored,
+    /// ERROR, CLIENT_ERROR, SERVER_ERROR or non zero binary status
+    Error,
+    /// Anything else the server may say, NOT_STORED for example
+    Other,
+}
+
+/// Get and set traffic in memcached text or binary protocol
+#[derive(Clone, Debug, Default)]
+pub struct Memcached {
+    binary: bool,
+    get_ratio: f64,
+    /// Everything a set carries after its key, built once
+    set_tail: Vec<u8>,
+    /// Picks keys and decides between get and set
+    keys: KeyGenerator,
+    /// Operation of request in flight
+    op: Op,
+}
+
+impl Memcached {
+    fn new(settings: &Settings, keys: KeyGenerator) -> Self {
+        let binary = settings.memcached.binary;
+        let mut set_tail = match binary {
+            true => Vec::new(),
+            false => format!(" 0 0 {}\r\n", settings.value_size).into_bytes(),
+        };
+        set_tail.resize(set_tail.len() + settings.value_size, b'x');
+        if !binary {
+            set_tail.extend_from_slice(b"\r\n");
+        }
+
+        Self {
+            binary,
+            get_ratio: settings.memcached.get_ratio,
+            set_tail,
+            keys,
+            op: Op::Get,
+        }
+    }
+}
+
+/// Bytes of the longest request given settings make, a set
+pub fn request_len(settings: &Settings) -> usize {
+    let memcached = Memcached::new(settings, KeyGenerator::default());
+    match memcached.binary {
+        true => BINARY_HEADER + SET_EXTRAS + KEY_LEN + memcached.set_tail.len(),
+        false => b"set ".len() + KEY_LEN + memcached.set_tail.len(),
+    }
+}
+
+impl Workload for Memcached {
+    fn for_connection(&self, connection: u64) -> Self {
+        Self::new(get_settings(), KeyGenerator::new(connection))
+    }
+
+    fn request(&mut self, buf: &mut [u8]) -> usize {
+        self.op = if self.keys.next_f64() < self.get_ratio {
+            Op::Get
+        } else {
+            Op::Set
+        };
+
+        let key = self.keys.next_key();
+
+        let mut out = Cursor::new(buf);
+        let written = if self.binary {
+            self.write_binary(&mut out, key)
+        } else {
+            self.write_text(&mut out, key)
+        };
+        written.expect("Memcached request does not fit into request buffer");
+        out.position() as usize
+    }
+
+    fn frame(&self, _request: &[u8], received: &[u8]) -> Frame {
+        match parse_reply(received, self.binary) {
+            Ok(Some((len, Reply::Error))) => Frame::Rejected(len),
+            Ok(Some((len, _))) => Frame::Complete(len),
+            Ok(None) => Frame::Incomplete,
+            Err(()) => Frame::Invalid,
+        }
+    }
+
+    fn validate(&mut self, _request: &[u8], response: &[u8]) -> Result<(), Failure> {
+        let Ok(Some((_, reply))) = parse_reply(response, self.binary) else {
+            return Err(Failure::Content);
+        };
+
+        // Error replies never get here, frame marks them as rejected
+        match (self.op, reply) {
+            (Op::Get, Reply::Hit | Reply::Miss) | (Op::Set, Reply::Stored) => Ok(()),
+            _ => Err(Failure::Content),
+        }
+    }
+
+    fn completed(&mut self, response: &[u8], stats: &mut Statistics) {
+        if self.op != Op::Get {
+            return;
+        }
+        match parse_reply(response, self.binary) {
+            Ok(Some((_, Reply::Hit))) => stats.increment_hits(),
+            Ok(Some((_, Reply::Miss))) => stats.increment_misses(),
+            _ => {}
+        }
+    }
+}
+
+impl Memcached {
+    fn write_text(&self, out: &mut Cursor<&mut [u8]>, key: u64) -> std::io::Result<()> {
+        match self.op {
+            Op::Get => {
+                out.write_all(b"get ")?;
+                write_key(out, key)?;
+                out.write_all(b"\r\n")
+            }
+            Op::Set => {
+                out.write_all(b"set ")?;
+                write_key(out, key)?;
+                out.write_all(&self.set_tail)
+            }
+        }
+    }
+
+    fn write_binary(&self, out: &mut Cursor<&mut [u8]>, key: u64) -> std::io::Result<()> {
+        let (opcode, extras, value) = match self.op {
+            Op::Get => (OP_GET, 0, &[][..]),
+            Op::Set => (OP_SET, SET_EXTRAS, &self.set_tail[..]),
+        };
+
+        out.write_all(&[REQUEST_MAGIC, opcode])?;
+        out.write_all(&(KEY_LEN as u16).to_be_bytes())?;
+        out.write_all(&[extras as u8, 0])?;
+        // vbucket
+        out.write_all(&0u16.to_be_bytes())?;
+        out.write_all(&((extras + KEY_LEN + value.len()) as u32).to_be_bytes())?;
+        // opaque and cas
+        out.write_all(&[0; 12])?;
+
+        out.write_all(&[0; SET_EXTRAS][..extras])?;
+        write_key(out, key)?;
+        out.write_all(value)
+    }
+}
+
+/// Length of the response at the start of buf and what it said, None while not all of it is in
+fn parse_reply(buf: &[u8], binary: bool) -> Result<Option<(usize, Reply)>, ()> {
+    if binary {
+        parse_binary(buf)
+    } else {
+        parse_text(buf)
+    }
+}
+
+fn parse_binary(buf: &[u8]) -> Result<Option<(usize, Reply)>, ()> {
+    if buf.len() < BINARY_HEADER {
+        return Ok(None);
+    }
+    if buf[0] != RESPONSE_MAGIC {
+        return Err(());
+    }
+
+    let status = u16::from_be_bytes([buf[6], buf[7]]);
+    let body = u32::from_be_bytes([buf[8], buf[9], buf[10], buf[11]]) as usize;
+    let total = BINARY_HEADER + body;
+    if buf.len() < total {
+        return Ok(None);
+    }
+
+    let reply = match (buf[1], status) {
+        (OP_GET, 0) => Reply::Hit,
+        (OP_GET, STATUS_KEY_NOT_FOUND) => Reply::Miss,
+        (OP_SET, 0) => Reply::Stored,
+        _ => Reply::Error,
+    };
+    Ok(Some((total, reply)))
+}
+
+fn parse_text(buf: &[u8]) -> Result<Option<(usize, Reply)>, ()> {
+    let mut offset = 0;
+    let mut hit = false;
+    loop {
+        let Some(line) = find_line(&buf[offset..]) else {
+            return Ok(None);
+        };
+        let text = &buf[offset..offset + line];
+        offset += line + 2;
+
+        if let Some(rest) = text.strip_prefix(b"VALUE ") {
+            // VALUE <key> <flags> <bytes> [<cas>], data and its CRLF follow
+            // Overflowing length can only come from a broken reply
+            offset = std::str::from_utf8(rest)
+                .ok()
+                .and_then(|x| x.split(' ').nth(2))
+                .and_then(|x| x.parse::<usize>().ok())
+                .and_then(|bytes| bytes.checked_add(offset + 2))
+                .ok_or(())?;
+            if buf.len() < offset {
+                return Ok(None);
+            }
+            hit = true;
+            continue;
+        }
+
+        let reply = match text {
+            b"END" if hit => Reply::Hit,
+            b"END" => Reply::Miss,
+            b"STORED" => Reply::Stored,
+            _ if text.starts_with(b"ERROR")
+                || text.starts_with(b"CLIENT_ERROR")
+                || text.starts_with(b"SERVER_ERROR") =>
+            {
+                Reply::Error
+            }
+            _ => Reply::Other,
+        };
+        return Ok(Some((offset, reply)));
+    }
+}
+
+/// Length of the first line without its CRLF
+fn find_line(buf: &[u8]) -> Option<usize> {
+    buf.windows(2).position(|x| x == b"\r\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    fn connection(binary: bool, get_ratio: f64) -> Memcached {
+        let settings = Settings {
+            memcached: MemcachedOptions { binary, get_ratio },
+            keys: 100,
+            value_size: 3,
+            ..Settings::default()
+        };
+        let len = request_len(&settings);
+        let mut memcached: Memcached = testing::connection(settings);
+        if get_ratio == 0.0 {
+            assert_eq!(memcached.request(&mut [0; 256]), len);
+        }
+        memcached
+    }
+
+    fn binary_response(opcode: u8, status: u16, body: &[u8]) -> Vec<u8> {
+        let mut response = vec![RESPONSE_MAGIC, opcode, 0, 0, 0, 0];
+        response.extend_from_slice(&status.to_be_bytes());
+        response.extend_from_slice(&(body.len() as u32).to_be_bytes());
+        response.extend_from_slice(&[0; 12]);
+        response.extend_from_slice(body);
+        response
+    }
+
+    #[test]
+    fn text_replies() {
+        for (response, reply) in [
+            (
+                &b"VALUE key:0000000001 0 3\r\nabc\r\nEND\r\n"[..],
+                Reply::Hit,
+            ),
+            (
+                b"VALUE key:0000000001 0 4 99\r\na\r\nb\r\nEND\r\n",
+                Reply::Hit,
+            ),
+            (b"END\r\n", Reply::Miss),
+            (b"STORED\r\n", Reply::Stored),
+            (b"NOT_STORED\r\n", Reply::Other),
+            (b"ERROR\r\n", Reply::Error),
+            (b"CLIENT_ERROR bad data chunk\r\n", Reply::Error),
+            (b"SERVER_ERROR out of memory\r\n", Reply::Error),
+        ] {
+            let text = String::from_utf8_lossy(response);
+            testing::assert_waits_for_all(&connection(false, 1.0), &[], response);
+            assert_eq!(
+                parse_text(response),
+                Ok(Some((response.len(), reply))),
+                "{text}"
+            );
+        }
+        assert_eq!(parse_text(b"VALUE key 0 x\r\n"), Err(()));
+        let huge = format!("VALUE key 0 {}\r\n", usize::MAX - 1);
+        assert_eq!(parse_text(huge.as_bytes()), Err(()));
+    }
+
+    #[test]
+    fn binary_replies() {
+        for (response, reply) in [
+            (binary_response(OP_GET, 0, b"\0\0\0\0abc"), Reply::Hit),
+            (
+                binary_response(OP_GET, STATUS_KEY_NOT_FOUND, b"Not found"),
+                Reply::Miss,
+            ),
+            (binary_response(OP_SET, 0, b""), Reply::Stored),
+            (
+                binary_response(OP_SET, 0x0082, b"Out of memory"),
+                Reply::Error,
+            ),
+        ] {
+            testing::assert_waits_for_all(&connection(true, 1.0), &[], &response);
+            assert_eq!(parse_binary(&response), Ok(Some((response.len(), reply))));
+        }
+        assert_eq!(parse_binary(&[REQUEST_MAGIC; BINARY_HEADER]), Err(()));
+    }
+
+    #[test]
+    fn text_requests() {
+        let mut buf = [0; 256];
+        let mut get = connection(false, 1.0);
+        let len = get.request(&mut buf);
+        assert!(buf[..len].starts_with(b"get key:00000000"));
+        assert!(buf[..len].ends_with(b"\r\n"));
+        assert_eq!(len, 4 + KEY_LEN + 2);
+
+        let mut set = connection(false, 0.0);
+        let len = set.request(&mut buf);
+        assert!(buf[..len].starts_with(b"set key:00000000"));
+        assert!(buf[..len].ends_with(b" 0 0 3\r\nxxx\r\n"));
+    }
+
+    #[test]
+    fn binary_requests() {
+        let mut buf = [0; 256];
+        let mut set = connection(true, 0.0);
+        let len = set.request(&mut buf);
+        assert_eq!(&buf[..2], [REQUEST_MAGIC, OP_SET]);
+        assert_eq!(buf[4], SET_EXTRAS as u8);
+        let body = u32::from_be_bytes(buf[8..12].try_into().unwrap()) as usize;
+        assert_eq!(BINARY_HEADER + body, len);
+        assert!(buf[..len].ends_with(b"xxx"));
+
+        let mut get = connection(true, 1.0);
+        let len = get.request(&mut buf);
+        assert_eq!(&buf[..2], [REQUEST_MAGIC, OP_GET]);
+        assert_eq!(len, BINARY_HEADER + KEY_LEN);
+    }
+
+    #[test]
+    fn errors_are_rejected() {
+        let mut set = connection(false, 0.0);
+        let response = b"SERVER_ERROR out of memory\r\n";
+        assert_eq!(set.frame(&[], response), Frame::Rejected(response.len()));
+        assert_eq!(set.frame(&[], b"STORED\r\n"), Frame::Complete(8));
+        assert_eq!(set.validate(&[], b"STORED\r\n"), Ok(()));
+        assert_eq!(set.validate(&[], b"END\r\n"), Err(Failure::Content));
+        assert_eq!(set.validate(&[], b"NOT_STORED\r\n"), Err(Failure::Content));
+    }
+}
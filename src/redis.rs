@@ -0,0 +1,324 @@
+use std::io::{Cursor, Write};
+
+use crate::{
+    keys::KeyGenerator,
+    settings::{get_settings, Settings},
+    stats::Statistics,
+    validate::Failure,
+    workload::{write_key, Frame, Workload, KEY_LEN},
+};
+
+/// Command every request of `-p redis` carries
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RedisCommand {
+    #[default]
+    Ping,
+    Get,
+    Set,
+}
+
+impl RedisCommand {
+    pub fn parse(value: &str) -> Option<Self> {
+        Some(match value {
+            "ping" => Self::Ping,
+            "get" => Self::Get,
+            "set" => Self::Set,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RedisOptions {
+    pub command: RedisCommand,
+    /// Commands sent in one write, response is complete once all of their replies are in
+    pub pipeline: usize,
+}
+
+impl Default for RedisOptions {
+    fn default() -> Self {
+        Self {
+            command: RedisCommand::Ping,
+            pipeline: 1,
+        }
+    }
+}
+
+/// Commands without their key and value, those follow as bulk strings
+const PING: &[u8] = b"*1\r\n$4\r\nPING\r\n";
+const GET: &[u8] = b"*2\r\n$3\r\nGET\r\n";
+const SET: &[u8] = b"*3\r\n$3\r\nSET\r\n";
+/// Key as a bulk string, `$14` and CRLF around the name
+const KEY_ARG: usize = 5 + KEY_LEN + 2;
+
+/// RESP speaking workload, commands go out as arrays of bulk strings
+#[derive(Clone, Debug, Default)]
+pub struct Redis {
+    command: RedisCommand,
+    pipeline: usize,
+    /// Value of every set as a whole bulk string, built once
+    value: Vec<u8>,
+    keys: KeyGenerator,
+}
+
+impl Redis {
+    fn new(settings: &Settings, keys: KeyGenerator) -> Self {
+        let mut value = format!("${}\r\n", settings.value_size).into_bytes();
+        value.resize(value.len() + settings.value_size, b'x');
+        value.extend_from_slice(b"\r\n");
+
+        Self {
+            command: settings.redis.command,
+            pipeline: settings.redis.pipeline.max(1),
+            value,
+            keys,
+        }
+    }
+
+    /// Bytes of one command, every command of a pipeline is as long
+    fn command_len(&self) -> usize {
+        match self.command {
+            RedisCommand::Ping => PING.len(),
+            RedisCommand::Get => GET.len() + KEY_ARG,
+            RedisCommand::Set => SET.len() + KEY_ARG + self.value.len(),
+        }
+    }
+}
+
+/// Bytes of the requests given settings make
+pub fn request_len(settings: &Settings) -> usize {
+    let redis = Redis::new(settings, KeyGenerator::default());
+    redis.command_len() * redis.pipeline
+}
+
+impl Workload for Redis {
+    fn for_connection(&self, connection: u64) -> Self {
+        Self::new(get_settings(), KeyGenerator::new(connection))
+    }
+
+    fn request(&mut self, buf: &mut [u8]) -> usize {
+        let mut out = Cursor::new(buf);
+        for _ in 0..self.pipeline {
+            let written = match self.command {
+                RedisCommand::Ping => out.write_all(PING),
+                RedisCommand::Get => out
+                    .write_all(GET)
+                    .and_then(|_| write_key_arg(&mut out, self.keys.next_key())),
+                RedisCommand::Set => out
+                    .write_all(SET)
+                    .and_then(|_| write_key_arg(&mut out, self.keys.next_key()))
+                    .and_then(|_| out.write_all(&self.value)),
+            };
+            written.expect("Redis pipeline does not fit into request buffer");
+        }
+        out.position() as usize
+    }
+
+    fn frame(&self, _request: &[u8], received: &[u8]) -> Frame {
+        let mut offset = 0;
+        let mut rejected = false;
+        for _ in 0..self.pipeline {
+            match parse_reply(&received[offset..]) {
+                Ok(Some(len)) => {
+                    rejected |= received[offset] == b'-';
+                    offset += len;
+                }
+                Ok(None) => return Frame::Incomplete,
+                Err(()) => return Frame::Invalid,
+            }
+        }
+
+        match rejected {
+            true => Frame::Rejected(offset),
+            false => Frame::Complete(offset),
+        }
+    }
+
+    fn validate(&mut self, _request: &[u8], response: &[u8]) -> Result<(), Failure> {
+        let mut rest = response;
+        for _ in 0..self.pipeline {
+            let Ok(Some(len)) = parse_reply(rest) else {
+                return Err(Failure::Content);
+            };
+            let (reply, next) = rest.split_at(len);
+            rest = next;
+
+            // Error replies never get here, frame marks them as rejected
+            let right = match self.command {
+                RedisCommand::Ping => reply == b"+PONG\r\n",
+                RedisCommand::Set => reply == b"+OK\r\n",
+                RedisCommand::Get => reply[0] == b'$',
+            };
+            if !right {
+                return Err(Failure::Content);
+            }
+        }
+        Ok(())
+    }
+
+    fn requests_per_exchange(&self) -> u64 {
+        self.pipeline as u64
+    }
+
+    fn completed(&mut self, response: &[u8], stats: &mut Statistics) {
+        if self.command != RedisCommand::Get {
+            return;
+        }
+
+        // Nil bulk string is a miss, any other one a hit
+        let mut rest = response;
+        while let Ok(Some(len)) = parse_reply(rest) {
+            match &rest[..len] {
+                b"$-1\r\n" => stats.increment_misses(),
+                [b'$', ..] => stats.increment_hits(),
+                _ => {}
+            }
+            rest = &rest[len..];
+        }
+    }
+}
+
+fn write_key_arg(out: &mut Cursor<&mut [u8]>, key: u64) -> std::io::Result<()> {
+    write!(out, "${KEY_LEN}\r\n")?;
+    write_key(out, key)?;
+    out.write_all(b"\r\n")
+}
+
+/// Length of the first reply in buf, None while not all of it is in
+fn parse_reply(buf: &[u8]) -> Result<Option<usize>, ()> {
+    let Some(&kind) = buf.first() else {
+        return Ok(None);
+    };
+    let Some(line) = buf.windows(2).position(|x| x == b"\r\n") else {
+        return Ok(None);
+    };
+    let header = line + 2;
+
+    match kind {
+        b'+' | b'-' | b':' => Ok(Some(header)),
+        b'$' => {
+            let len = parse_len(&buf[1..line])?;
+            if len < 0 {
+                return Ok(Some(header));
+            }
+            let total = header + len as usize + 2;
+            Ok((buf.len() >= total).then_some(total))
+        }
+        b'*' => {
+            let count = parse_len(&buf[1..line])?;
+            let mut offset = header;
+            for _ in 0..count.max(0) {
+                match parse_reply(&buf[offset..])? {
+                    Some(len) => offset += len,
+                    None => return Ok(None),
+                }
+            }
+            Ok(Some(offset))
+        }
+        _ => Err(()),
+    }
+}
+
+fn parse_len(digits: &[u8]) -> Result<i64, ()> {
+    std::str::from_utf8(digits)
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .ok_or(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{keys::KeyDistribution, testing};
+
+    fn connection(command: RedisCommand, pipeline: usize) -> Redis {
+        let settings = Settings {
+            redis: RedisOptions { command, pipeline },
+            keys: 100,
+            key_distribution: KeyDistribution::Sequential,
+            value_size: 3,
+            ..Settings::default()
+        };
+        testing::connection(settings)
+    }
+
+    #[test]
+    fn replies() {
+        for reply in [
+            &b"+PONG\r\n"[..],
+            b"-ERR unknown command\r\n",
+            b":42\r\n",
+            b"$3\r\nabc\r\n",
+            b"$0\r\n\r\n",
+            b"$-1\r\n",
+            b"*-1\r\n",
+            b"*0\r\n",
+            b"*2\r\n$1\r\na\r\n:1\r\n",
+            b"*2\r\n*1\r\n+a\r\n$-1\r\n",
+        ] {
+            let text = String::from_utf8_lossy(reply);
+            for split in 0..reply.len() {
+                assert_eq!(parse_reply(&reply[..split]), Ok(None), "{text} at {split}");
+            }
+            assert_eq!(parse_reply(reply), Ok(Some(reply.len())), "{text}");
+
+            let mut more = reply.to_vec();
+            more.extend_from_slice(b"+OK\r\n");
+            assert_eq!(parse_reply(&more), Ok(Some(reply.len())), "{text}");
+        }
+    }
+
+    #[test]
+    fn malformed_replies() {
+        for reply in [&b"?\r\n"[..], b"$x\r\nabc\r\n", b"*\r\n", b"*1\r\n!\r\n"] {
+            assert_eq!(parse_reply(reply), Err(()));
+        }
+    }
+
+    #[test]
+    fn requests() {
+        let mut buf = [0; 256];
+        let mut redis = connection(RedisCommand::Get, 2);
+        let len = redis.request(&mut buf);
+        assert_eq!(
+            &buf[..len],
+            b"*2\r\n$3\r\nGET\r\n$14\r\nkey:0000000001\r\n*2\r\n$3\r\nGET\r\n$14\r\nkey:0000000002\r\n"
+        );
+        assert_eq!(len, redis.command_len() * 2);
+
+        let mut redis = connection(RedisCommand::Set, 1);
+        let len = redis.request(&mut buf);
+        assert_eq!(
+            &buf[..len],
+            b"*3\r\n$3\r\nSET\r\n$14\r\nkey:0000000001\r\n$3\r\nxxx\r\n"
+        );
+        assert_eq!(len, redis.command_len());
+    }
+
+    #[test]
+    fn pipeline_waits_for_every_reply() {
+        let redis = connection(RedisCommand::Ping, 2);
+        let response = b"+PONG\r\n+PONG\r\n";
+        testing::assert_waits_for_all(&redis, &[], response);
+        assert_eq!(redis.frame(&[], response), Frame::Complete(response.len()));
+        assert_eq!(redis.frame(&[], b"+PONG\r\n?\r\n"), Frame::Invalid);
+    }
+
+    #[test]
+    fn error_reply_is_rejected() {
+        let mut redis = connection(RedisCommand::Set, 2);
+        let response = b"+OK\r\n-OOM command not allowed\r\n";
+        assert_eq!(redis.frame(&[], response), Frame::Rejected(response.len()));
+        assert_eq!(redis.validate(&[], b"+OK\r\n+OK\r\n"), Ok(()));
+        assert_eq!(redis.validate(&[], b"+OK\r\n:1\r\n"), Err(Failure::Content));
+    }
+
+    #[test]
+    fn hits_and_misses() {
+        let mut redis = connection(RedisCommand::Get, 2);
+        let mut stats = Statistics::default();
+        redis.completed(b"$-1\r\n$1\r\nx\r\n", &mut stats);
+        assert_eq!(stats.successful_returns(), 0);
+        assert_eq!((stats.hits(), stats.misses()), (1, 1));
+    }
+}
@@ -0,0 +1,118 @@
+use crate::{
+    settings::{get_settings, Search},
+    stats::{format_nanos, Statistics},
+    stop,
+    worker::{self, Round},
+    workload::Workload,
+};
+
+struct RoundResult {
+    offered: Option<f64>,
+    achieved: f64,
+    p99: u64,
+    /// Share of failed exchanges in percent
+    errors: f64,
+    passed: bool,
+}
+
+impl RoundResult {
+    fn measure<W: Workload>(search: &Search, rate: Option<f64>, workload: &W) -> RoundResult {
+        let stats = worker::run(
+            Round {
+                burn_time: search.round_time,
+                rate,
+                requests: None,
+            },
+            workload,
+        );
+        RoundResult::from_stats(search, rate, &stats)
+    }
+
+    fn from_stats(search: &Search, offered: Option<f64>, stats: &Statistics) -> RoundResult {
+        let good = stats.successful_returns();
+        let all = good + stats.errors();
+        let errors = if all == 0 {
+            100.0
+        } else {
+            stats.errors() as f64 * 100.0 / all as f64
+        };
+        let p99 = stats.latency_at(0.99);
+
+        let latency_ok = search
+            .p99
+            .is_none_or(|limit| p99 <= limit.as_nanos() as u64);
+
+        RoundResult {
+            offered,
+            achieved: stats.good_per_second(),
+            p99,
+            errors,
+            passed: good > 0 && latency_ok && errors <= search.max_errors,
+        }
+    }
+}
+
+/// Binary search offered rate for highest one that still keeps within SLO
+///
+/// First round runs unthrottled to find the ceiling, rest of them halve the range between
+/// highest passing and lowest failing rate. Returns the highest rate that passed
+pub fn search<W: Workload>(workload: &W) -> Option<f64> {
+    let settings = get_settings();
+    let search = settings.search.as_ref().expect("Search is not configured");
+
+    println!("SEARCH STARTED");
+    println!(
+        "{:>5} {:>12} {:>12} {:>12} {:>8} {:>4}",
+        "round", "offered/s", "achieved/s", "p99", "errors%", "slo"
+    );
+
+    let ceiling = RoundResult::measure(search, None, workload);
+    print_round(0, &ceiling);
+
+    let mut low = 0.0;
+    let mut high = ceiling.achieved;
+    let mut best = ceiling.passed.then_some(ceiling.achieved);
+
+    // Without a single right result there is no rate to halve
+    if best.is_none() && ceiling.achieved > 0.0 {
+        for round in 1..=search.rounds {
+            if stop::requested() {
+                break;
+            }
+
+            let rate = (low + high) / 2.0;
+            let result = RoundResult::measure(search, Some(rate), workload);
+            print_round(round, &result);
+
+            if result.passed {
+                low = rate;
+                best = Some(rate);
+            } else {
+                high = rate;
+            }
+        }
+    }
+
+    println!("SEARCH ENDED");
+    match best {
+        Some(rate) => println!("Highest sustainable rate: {rate:.0} requests per second"),
+        None => println!("No rate kept within SLO"),
+    }
+    best
+}
+
+fn print_round(round: usize, result: &RoundResult) {
+    let offered = match result.offered {
+        Some(rate) => format!("{rate:.0}"),
+        None => "max".to_string(),
+    };
+    println!(
+        "{:>5} {:>12} {:>12.0} {:>12} {:>8.3} {:>4}",
+        round,
+        offered,
+        result.achieved,
+        format_nanos(result.p99 as f64),
+        result.errors,
+        if result.passed { "ok" } else { "fail" }
+    );
+}
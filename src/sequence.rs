@@ -0,0 +1,116 @@
+/// Bytes at the start of a UDP payload carrying its sequence number
+pub const SEQUENCE_LEN: usize = size_of::<u64>();
+
+/// What a response with given sequence number turned out to be
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Arrival {
+    /// Answers the newest attempt of the request in flight
+    Fresh,
+    /// Answers an earlier attempt of the request in flight, it still counts as its answer
+    Earlier,
+    /// Answers a request that has been done with already
+    Stale,
+    /// Same sequence number has been answered before
+    Duplicate,
+    /// Sequence number that was never sent
+    Bogus,
+}
+
+/// Sequence numbers of one UDP connection
+///
+/// Every datagram sent, retransmissions included, gets a new number. Responses are matched
+/// against a 64 entry window of recent numbers, anything older is considered stale.
+#[derive(Debug, Default)]
+pub struct SequenceTracker {
+    /// Number next attempt will get
+    next: u64,
+    /// Number of the first attempt of the request in flight
+    request_start: u64,
+    /// Bit n set means response for `next - 1 - n` arrived
+    answered: u64,
+}
+
+impl SequenceTracker {
+    /// Number for the next datagram, retransmissions pass false to stay on the same request
+    pub fn attempt(&mut self, new_request: bool) -> u64 {
+        let seq = self.next;
+        if new_request {
+            self.request_start = seq;
+        }
+        self.next += 1;
+        self.answered <<= 1;
+        seq
+    }
+
+    pub fn arrived(&mut self, seq: u64) -> Arrival {
+        if seq >= self.next {
+            return Arrival::Bogus;
+        }
+
+        let age = self.next - 1 - seq;
+        if age >= u64::BITS as u64 {
+            return Arrival::Stale;
+        }
+
+        let bit = 1 << age;
+        if self.answered & bit != 0 {
+            return Arrival::Duplicate;
+        }
+        self.answered |= bit;
+
+        if age == 0 {
+            Arrival::Fresh
+        } else if seq >= self.request_start {
+            Arrival::Earlier
+        } else {
+            Arrival::Stale
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fresh_and_duplicate() {
+        let mut tracker = SequenceTracker::default();
+        let seq = tracker.attempt(true);
+        assert_eq!(tracker.arrived(seq), Arrival::Fresh);
+        assert_eq!(tracker.arrived(seq), Arrival::Duplicate);
+        assert_eq!(tracker.arrived(seq + 1), Arrival::Bogus);
+    }
+
+    #[test]
+    fn retransmission_answers_earlier_attempt() {
+        let mut tracker = SequenceTracker::default();
+        let first = tracker.attempt(true);
+        let retry = tracker.attempt(false);
+        assert_eq!(tracker.arrived(first), Arrival::Earlier);
+        assert_eq!(tracker.arrived(retry), Arrival::Fresh);
+        assert_eq!(tracker.arrived(first), Arrival::Duplicate);
+    }
+
+    #[test]
+    fn previous_request_is_stale() {
+        let mut tracker = SequenceTracker::default();
+        let old = tracker.attempt(true);
+        let new = tracker.attempt(true);
+        assert_eq!(tracker.arrived(old), Arrival::Stale);
+        assert_eq!(tracker.arrived(old), Arrival::Duplicate);
+        assert_eq!(tracker.arrived(new), Arrival::Fresh);
+    }
+
+    #[test]
+    fn outside_window_is_stale() {
+        let mut tracker = SequenceTracker::default();
+        let old = tracker.attempt(true);
+        for _ in 0..u64::BITS {
+            tracker.attempt(false);
+        }
+        // Window no longer remembers it, so a repeat can not be told apart
+        assert_eq!(tracker.arrived(old), Arrival::Stale);
+        assert_eq!(tracker.arrived(old), Arrival::Stale);
+        assert_eq!(tracker.arrived(old + 1), Arrival::Earlier);
+    }
+}
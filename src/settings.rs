@@ -1,15 +1,35 @@
 use std::{
+    cell::RefCell,
     env::args,
     net::{Ipv4Addr, SocketAddr, SocketAddrV4},
     num::NonZero,
-    sync::OnceLock,
+    sync::Arc,
     thread::available_parallelism,
     time::Duration,
 };
 
 use liburing_rs::__kernel_timespec;
+use rustls::pki_types::ServerName;
 
-static SETTINGS: OnceLock<Settings> = OnceLock::new();
+use crate::{
+    framed::{self, parse_delimiter, LengthPrefix},
+    grpc::GrpcOptions,
+    h2::{self, Http2Options},
+    keys::KeyDistribution,
+    memcached::{self, MemcachedOptions},
+    redis::{self, RedisCommand, RedisOptions},
+    sockopt::{SockOpt, SocketOptions},
+    tasks::REQUEST_BUF,
+    tls::{self, TlsOptions},
+    validate::Validation,
+    workload::{WorkloadKind, MAX_KEYS},
+    ws,
+};
+
+thread_local! {
+    /// Settings of the run this thread takes part in, see `enter`
+    static CURRENT: RefCell<Option<Arc<Settings>>> = const { RefCell::new(None) };
+}
 
 #[derive(Clone, Copy, Debug)]
 pub enum Protocol {
@@ -17,31 +37,214 @@ pub enum Protocol {
     Udp,
 }
 
-#[derive(Debug)]
+/// Search for highest rate that still keeps within given limits
+#[derive(Clone, Debug)]
+pub struct Search {
+    pub p99: Option<Duration>,
+    /// Highest acceptable share of failed exchanges in percent
+    pub max_errors: f64,
+    pub rounds: usize,
+    pub round_time: __kernel_timespec,
+}
+
+#[derive(Clone, Debug)]
 pub struct Settings {
     pub burn_time: __kernel_timespec,
     pub connections: usize,
     pub target: SocketAddr,
     pub proto: Protocol,
+    pub workload: WorkloadKind,
+    /// Keys key/value workloads pick from
+    pub keys: u64,
+    pub key_distribution: KeyDistribution,
+    /// Skew of zipf key distribution, higher means fewer hotter keys
+    pub zipf_theta: f64,
+    /// Bytes of every value key/value workloads store and of every WebSocket message
+    pub value_size: usize,
+    /// Request path of HTTP based workloads
+    pub path: String,
+    pub redis: RedisOptions,
+    pub memcached: MemcachedOptions,
+    pub h2: Http2Options,
+    pub grpc: GrpcOptions,
+    pub length_prefix: LengthPrefix,
+    /// Bytes ending every `-p delimited` message
+    pub delimiter: Vec<u8>,
     pub threads: NonZero<usize>,
     pub debug: bool,
+    /// Print right results of every connection in final report
+    pub connections_table: bool,
+    /// Read the clock for every completion instead of once per batch
+    pub precise: bool,
+    /// Ask kernel for software send and receive timestamps to measure its view of RTT
+    pub kernel_timestamps: bool,
+    /// Put a `RequestHeader` at the start of every request
+    pub header: bool,
+    pub validate: Validation,
+    pub timeout: Option<__kernel_timespec>,
+    pub ramp: Option<Duration>,
+    pub ramp_wait: bool,
+    /// Offered requests per second across all connections, None means as fast as possible
+    pub rate: Option<f64>,
+    /// End run after this many successful exchanges instead of after burn time
+    pub requests: Option<u64>,
+    pub search: Option<Search>,
+    /// Connections speak TLS with records handled by the kernel
+    pub tls: Option<TlsOptions>,
+    pub socket_options: SocketOptions,
+    /// Filled from `socket_options` by `resolve`
+    pub sock_opts: Vec<SockOpt>,
+    /// Filled from `socket_options` by `resolve`, set before every read
+    pub quickack: Option<SockOpt>,
 }
 
 impl Settings {
     pub fn connections_per_thread(&self) -> usize {
         self.connections.div_ceil(self.threads.get())
     }
+
+    /// Fill in whatever depends on other settings, done right before a run
+    pub fn resolve(&mut self) {
+        // Lost datagram would stall its connection for good without a timeout
+        if let (Protocol::Udp, None) = (self.proto, self.timeout) {
+            self.timeout = Some(__kernel_timespec::from(Duration::from_secs(1)));
+        }
+
+        if self.validate == Validation::Header {
+            self.header = true;
+        }
+        if self.kernel_timestamps {
+            self.socket_options.timestamping = true;
+        }
+
+        self.sock_opts = self.socket_options.resolve(&self.target);
+        self.quickack = self.socket_options.quickack();
+    }
+
+    /// Catch settings no run can go with before any worker starts
+    pub fn check(&self) -> Result<(), String> {
+        if self.rate.is_some_and(|rate| rate <= 0.0) {
+            return Err("Rate has to be a positive number".to_string());
+        }
+        // Zipf sampling divides by it, NaN and infinity break it just the same
+        if !(self.zipf_theta > 0.0 && self.zipf_theta.is_finite()) {
+            return Err("Zipf theta has to be a positive number".to_string());
+        }
+        if self.keys > MAX_KEYS {
+            return Err(format!("At most {MAX_KEYS} keys are supported"));
+        }
+        // Calls go to /service/Method, default path would just get UNIMPLEMENTED back
+        let method = self.path.strip_prefix('/').and_then(|x| x.split_once('/'));
+        if self.workload == WorkloadKind::Grpc
+            && !method.is_some_and(|(service, method)| !service.is_empty() && !method.is_empty())
+        {
+            return Err("gRPC needs --path /service/Method".to_string());
+        }
+
+        let tcp_only = &self.socket_options;
+        if let Protocol::Udp = self.proto {
+            if tcp_only.nodelay.is_some() || tcp_only.quickack.is_some() {
+                return Err("--nodelay and --quickack can only be used with tcp".to_string());
+            }
+        }
+
+        if let Some(tls) = self.tls.as_ref() {
+            if let Protocol::Udp = self.proto {
+                return Err("TLS needs a TCP connection".to_string());
+            }
+            if let Some(name) = tls.server_name.as_deref() {
+                if ServerName::try_from(name).is_err() {
+                    return Err(format!("Invalid TLS server name {name}"));
+                }
+            }
+            if let Err(err) = tls::check_kernel() {
+                return Err(format!(
+                    "Kernel TLS is not available, is the tls module loaded? {err}"
+                ));
+            }
+        }
+
+        match self.workload {
+            WorkloadKind::Length if self.value_size as u64 > self.length_prefix.max_len() => {
+                return Err("Value size does not fit into the length prefix".to_string());
+            }
+            WorkloadKind::Delimited if framed::alphabet(&self.delimiter).is_empty() => {
+                return Err("Delimiter leaves nothing to build messages from".to_string());
+            }
+            _ => {}
+        }
+
+        let request = match self.workload {
+            WorkloadKind::Redis => redis::request_len(self),
+            WorkloadKind::Memcached => memcached::request_len(self),
+            WorkloadKind::WebSocket => ws::request_len(self),
+            WorkloadKind::Http2 | WorkloadKind::Grpc => h2::request_len(self),
+            WorkloadKind::Length | WorkloadKind::Delimited => framed::request_len(self),
+            // Echo fills whatever buffer it gets
+            WorkloadKind::Echo => 0,
+        };
+        if request > REQUEST_BUF {
+            return Err(format!(
+                "Requests would take {request} bytes, request buffer holds {REQUEST_BUF}"
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            burn_time: __kernel_timespec::from(Duration::from_secs(10)),
+            connections: 1024,
+            threads: available_parallelism().unwrap_or(NonZero::new(1).unwrap()),
+            target: SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 6664)),
+            proto: Protocol::Tcp,
+            workload: WorkloadKind::Echo,
+            keys: 1000,
+            key_distribution: KeyDistribution::Uniform,
+            zipf_theta: 0.99,
+            value_size: 32,
+            path: "/".to_string(),
+            redis: RedisOptions::default(),
+            memcached: MemcachedOptions::default(),
+            h2: Http2Options::default(),
+            grpc: GrpcOptions::default(),
+            length_prefix: LengthPrefix::default(),
+            delimiter: b"\n".to_vec(),
+            debug: false,
+            connections_table: false,
+            precise: false,
+            kernel_timestamps: false,
+            header: false,
+            validate: Validation::Full,
+            timeout: None,
+            ramp: None,
+            ramp_wait: false,
+            rate: None,
+            requests: None,
+            search: None,
+            tls: None,
+            socket_options: SocketOptions::default(),
+            sock_opts: Vec::new(),
+            quickack: None,
+        }
+    }
 }
 
-pub fn load() -> &'static Settings {
-    let mut settings = Settings {
-        burn_time: __kernel_timespec::from(Duration::from_secs(10)),
-        connections: 1024,
-        threads: available_parallelism().unwrap_or(NonZero::new(1).unwrap()),
-        target: SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 6664)),
-        proto: Protocol::Tcp,
-        debug: false,
+/// Parse command line, see usage.txt
+pub fn from_args() -> Settings {
+    let mut settings = Settings::default();
+
+    let mut search_enabled = false;
+    let mut search = Search {
+        p99: None,
+        max_errors: 1.0,
+        rounds: 8,
+        round_time: __kernel_timespec::from(Duration::from_secs(5)),
     };
+    let mut tls_enabled = false;
+    let mut tls = TlsOptions::default();
 
     let mut args = args().collect::<Vec<String>>();
 
@@ -56,23 +259,393 @@ pub fn load() -> &'static Settings {
         match (arg.as_str(), val.as_str()) {
             ("-c", v) => settings.connections = v.parse().expect("Invalid value for connections"),
             ("-b", v) => {
-                settings.burn_time = __kernel_timespec::from(Duration::from_secs(
-                    v.parse().expect("Please provide valid seconds num"),
+                settings.burn_time = __kernel_timespec::from(
+                    parse_duration(v, SECOND).expect("Please provide valid burn time"),
+                )
+            }
+            ("--timeout", v) => {
+                settings.timeout = Some(__kernel_timespec::from(
+                    parse_duration(v, MILLISECOND).expect("Please provide valid timeout"),
                 ))
             }
+            ("--ramp", v) => {
+                settings.ramp =
+                    Some(parse_duration(v, SECOND).expect("Please provide valid ramp time"))
+            }
+            ("--ramp-wait", "yes") => settings.ramp_wait = true,
+            ("--rate", v) => {
+                let rate = v.parse().ok().filter(|&rate: &f64| rate > 0.0);
+                settings.rate = Some(rate.expect("Rate has to be a positive number"))
+            }
+            ("--search", "yes") => search_enabled = true,
+            ("--slo-p99", v) => {
+                search.p99 =
+                    Some(parse_duration(v, MICROSECOND).expect("Please provide valid latency"))
+            }
+            ("--slo-errors", v) => search.max_errors = v.parse().expect("Invalid error percent"),
+            ("--rounds", v) => search.rounds = v.parse().expect("Invalid number of rounds"),
+            ("--round-time", v) => {
+                search.round_time = __kernel_timespec::from(
+                    parse_duration(v, SECOND).expect("Please provide valid round time"),
+                )
+            }
+            ("--tls", "yes") => tls_enabled = true,
+            ("--tls-name", v) => tls.server_name = Some(v.to_string()),
+            ("-n", v) => settings.requests = Some(v.parse().expect("Invalid number of requests")),
             ("-t", v) => settings.threads = v.parse().expect("Could not parse threads"),
             ("-p", "udp") => settings.proto = Protocol::Udp,
             ("-p", "tcp") => settings.proto = Protocol::Tcp,
+            ("-p", "redis") => {
+                settings.proto = Protocol::Tcp;
+                settings.workload = WorkloadKind::Redis;
+            }
+            ("-p", "memcached") => {
+                settings.proto = Protocol::Tcp;
+                settings.workload = WorkloadKind::Memcached;
+            }
+            ("-p", "memcached-binary") => {
+                settings.proto = Protocol::Tcp;
+                settings.workload = WorkloadKind::Memcached;
+                settings.memcached.binary = true;
+            }
+            ("-p", "ws") => {
+                settings.proto = Protocol::Tcp;
+                settings.workload = WorkloadKind::WebSocket;
+            }
+            ("-p", "h2c") => {
+                settings.proto = Protocol::Tcp;
+                settings.workload = WorkloadKind::Http2;
+            }
+            ("-p", "grpc") => {
+                settings.proto = Protocol::Tcp;
+                settings.workload = WorkloadKind::Grpc;
+            }
+            ("-p", "length") => {
+                settings.proto = Protocol::Tcp;
+                settings.workload = WorkloadKind::Length;
+            }
+            ("-p", "delimited") => {
+                settings.proto = Protocol::Tcp;
+                settings.workload = WorkloadKind::Delimited;
+            }
+            ("--path", v) => settings.path = v.to_string(),
+            ("--get-ratio", v) => {
+                settings.memcached.get_ratio = v.parse().expect("Invalid get ratio")
+            }
+            ("--command", v) => {
+                settings.redis.command = RedisCommand::parse(v).expect("Invalid redis command")
+            }
+            ("--pipeline", v) => {
+                settings.redis.pipeline = v.parse().expect("Invalid pipeline depth")
+            }
+            ("--streams", v) => settings.h2.streams = v.parse().expect("Invalid number of streams"),
+            ("--payload", v) => {
+                settings.grpc.payload = std::fs::read(v).expect("Could not read payload file")
+            }
+            ("--length-prefix", v) => {
+                settings.length_prefix = LengthPrefix::parse(v).expect("Invalid length prefix")
+            }
+            ("--delimiter", v) => {
+                settings.delimiter = parse_delimiter(v).expect("Invalid delimiter")
+            }
+            ("--keys", v) => settings.keys = v.parse().expect("Invalid number of keys"),
+            ("--key-distribution", v) => {
+                settings.key_distribution =
+                    KeyDistribution::parse(v).expect("Invalid key distribution")
+            }
+            ("--zipf-theta", v) => {
+                let theta = v.parse().ok().filter(|&theta: &f64| theta > 0.0);
+                settings.zipf_theta = theta.expect("Zipf theta has to be a positive number")
+            }
+            ("--value-size", v) => settings.value_size = v.parse().expect("Invalid value size"),
             ("--debug", "yes") => settings.debug = true,
+            ("--connections-table", "yes") => settings.connections_table = true,
+            ("--precise", "yes") => settings.precise = true,
+            ("--kernel-timestamps", "yes") => settings.kernel_timestamps = true,
+            ("--header", "yes") => settings.header = true,
+            ("--validate", v) => {
+                settings.validate = Validation::parse(v).expect("Invalid validation mode")
+            }
+            ("--nodelay", "yes") => settings.socket_options.nodelay = Some(true),
+            ("--nodelay", "no") => settings.socket_options.nodelay = Some(false),
+            ("--quickack", "yes") => settings.socket_options.quickack = Some(true),
+            ("--quickack", "no") => settings.socket_options.quickack = Some(false),
+            ("--sndbuf", v) => {
+                settings.socket_options.send_buf =
+                    Some(v.parse().expect("Invalid send buffer size"))
+            }
+            ("--rcvbuf", v) => {
+                settings.socket_options.recv_buf =
+                    Some(v.parse().expect("Invalid receive buffer size"))
+            }
+            ("--busy-poll", v) => {
+                settings.socket_options.busy_poll =
+                    Some(v.parse().expect("Invalid busy poll microseconds"))
+            }
+            ("--linger", v) => {
+                settings.socket_options.linger = Some(v.parse().expect("Invalid linger seconds"))
+            }
+            ("--tos", v) => {
+                settings.socket_options.tos = Some(v.parse().expect("Invalid TOS value"))
+            }
             (addr, _) => settings.target = addr.parse().expect("Invalid socket address"),
         }
     }
 
-    SETTINGS.set(settings).expect("Could not set up settings");
-    unsafe { SETTINGS.get().unwrap_unchecked() }
+    if search_enabled {
+        settings.search = Some(search);
+    }
+    if tls_enabled {
+        settings.tls = Some(tls);
+    }
+
+    if let Err(err) = settings.check() {
+        eprintln!("{err}, see usage.txt");
+        std::process::exit(2);
+    }
+
+    settings
+}
+
+const MICROSECOND: Duration = Duration::from_micros(1);
+const MILLISECOND: Duration = Duration::from_millis(1);
+const SECOND: Duration = Duration::from_secs(1);
+
+/// Parse durations like `500ms`, `90s` or `5m`, bare numbers are taken in given unit
+fn parse_duration(value: &str, unit: Duration) -> Option<Duration> {
+    let split = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(value.len());
+    let (num, suffix) = value.split_at(split);
+    let num: f64 = num.parse().ok()?;
+
+    let unit = match suffix {
+        "" => unit,
+        "ns" => Duration::from_nanos(1),
+        "us" | "µs" => MICROSECOND,
+        "ms" => MILLISECOND,
+        "s" => SECOND,
+        "m" => SECOND * 60,
+        "h" => SECOND * 3600,
+        _ => return None,
+    };
+
+    // Too long to be a Duration at all
+    Duration::try_from_secs_f64(unit.as_secs_f64() * num).ok()?;
+    Some(unit.mul_f64(num))
+}
+
+/// Make settings current for the calling thread, every thread of a run has to do this first
+///
+/// They stay current until the next call or until the thread ends
+pub(crate) fn enter(settings: Arc<Settings>) {
+    CURRENT.set(Some(settings));
+}
+
+/// Settings current on the calling thread, for handing them to threads it spawns
+pub(crate) fn current() -> Arc<Settings> {
+    CURRENT.with_borrow(|current| {
+        current
+            .clone()
+            .expect("Settings were not entered on this thread")
+    })
 }
 
+/// Reference is good until settings are entered again on this thread, nothing may hold on
+/// to it from one run to the next. Crate private for that reason, `Benchmark` is the only
+/// one entering settings outside of worker threads
 #[inline]
-pub fn get_settings() -> &'static Settings {
-    SETTINGS.get().unwrap()
+pub(crate) fn get_settings() -> &'static Settings {
+    CURRENT.with_borrow(|current| {
+        let settings = current
+            .as_deref()
+            .expect("Settings were not entered on this thread");
+        // CURRENT keeps the Arc alive until the next `enter`
+        unsafe { &*(settings as *const Settings) }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations() {
+        for (value, duration) in [
+            ("500ms", MILLISECOND * 500),
+            ("90s", SECOND * 90),
+            ("1.5m", SECOND * 90),
+            ("2h", SECOND * 7200),
+            ("250us", MICROSECOND * 250),
+            ("250µs", MICROSECOND * 250),
+            ("100ns", Duration::from_nanos(100)),
+            ("0s", Duration::ZERO),
+        ] {
+            assert_eq!(parse_duration(value, SECOND), Some(duration), "{value}");
+        }
+    }
+
+    #[test]
+    fn bare_number_takes_given_unit() {
+        assert_eq!(parse_duration("3", SECOND), Some(SECOND * 3));
+        assert_eq!(parse_duration("3", MILLISECOND), Some(MILLISECOND * 3));
+        assert_eq!(parse_duration("0.5", SECOND), Some(MILLISECOND * 500));
+    }
+
+    #[test]
+    fn bad_durations() {
+        let huge = "9".repeat(30) + "h";
+        for value in ["", "s", "ms", "1x", "1 s", "-1s", "1.2.3s", "1e3s", &huge] {
+            assert_eq!(parse_duration(value, SECOND), None, "{value}");
+        }
+    }
+
+    /// Settings whose requests carry values of this size
+    fn sized(workload: WorkloadKind, value_size: usize) -> Settings {
+        Settings {
+            workload,
+            value_size,
+            ..Settings::default()
+        }
+    }
+
+    #[test]
+    fn checks_redis_request_size() {
+        assert_eq!(Settings::default().check(), Ok(()));
+
+        let set = |value_size, pipeline| Settings {
+            redis: RedisOptions {
+                command: RedisCommand::Set,
+                pipeline,
+            },
+            ..sized(WorkloadKind::Redis, value_size)
+        };
+        assert!(set(REQUEST_BUF, 1).check().is_err());
+        assert_eq!(set(32, 1).check(), Ok(()));
+        // Pipeline adds up as well
+        assert!(set(32, 100).check().is_err());
+    }
+
+    #[test]
+    fn checks_memcached_request_size() {
+        assert!(sized(WorkloadKind::Memcached, REQUEST_BUF).check().is_err());
+        assert_eq!(sized(WorkloadKind::Memcached, 32).check(), Ok(()));
+    }
+
+    #[test]
+    fn checks_websocket_request_size() {
+        assert!(sized(WorkloadKind::WebSocket, REQUEST_BUF).check().is_err());
+        assert_eq!(sized(WorkloadKind::WebSocket, 32).check(), Ok(()));
+    }
+
+    #[test]
+    fn checks_h2_request_size() {
+        let streams = |streams| Settings {
+            h2: Http2Options { streams },
+            ..sized(WorkloadKind::Http2, 32)
+        };
+        assert!(streams(1000).check().is_err());
+        assert_eq!(streams(8).check(), Ok(()));
+    }
+
+    #[test]
+    fn checks_framing() {
+        for workload in [WorkloadKind::Length, WorkloadKind::Delimited] {
+            assert!(
+                sized(workload, REQUEST_BUF).check().is_err(),
+                "{workload:?}"
+            );
+            assert_eq!(sized(workload, 32).check(), Ok(()), "{workload:?}");
+        }
+
+        let settings = Settings {
+            workload: WorkloadKind::Length,
+            length_prefix: LengthPrefix::parse("u8").unwrap(),
+            value_size: 256,
+            ..Settings::default()
+        };
+        assert!(settings.check().is_err());
+
+        let settings = Settings {
+            workload: WorkloadKind::Delimited,
+            delimiter: (b'0'..=b'9').chain(b'a'..=b'z').collect(),
+            ..Settings::default()
+        };
+        assert!(settings.check().is_err());
+    }
+
+    #[test]
+    fn checks_grpc_path() {
+        for (path, ok) in [
+            ("/", false),
+            ("/svc", false),
+            ("/svc/", false),
+            ("/svc/Call", true),
+        ] {
+            let settings = Settings {
+                workload: WorkloadKind::Grpc,
+                path: path.to_string(),
+                ..Settings::default()
+            };
+            assert_eq!(settings.check().is_ok(), ok, "{path}");
+        }
+    }
+
+    #[test]
+    fn checks_tls_name() {
+        let settings = Settings {
+            tls: Some(TlsOptions {
+                server_name: Some("not a name".to_string()),
+            }),
+            ..Settings::default()
+        };
+        let err = settings.check().unwrap_err();
+        assert!(err.contains("server name"), "{err}");
+    }
+
+    #[test]
+    fn checks_tcp_only_options() {
+        let mut settings = Settings {
+            proto: Protocol::Udp,
+            socket_options: SocketOptions {
+                quickack: Some(true),
+                ..SocketOptions::default()
+            },
+            ..Settings::default()
+        };
+        assert!(settings.check().is_err());
+
+        settings.proto = Protocol::Tcp;
+        assert_eq!(settings.check(), Ok(()));
+        settings.resolve();
+        assert!(settings.quickack.is_some());
+    }
+
+    #[test]
+    fn checks_rate() {
+        for rate in [0.0, -1.0] {
+            let settings = Settings {
+                rate: Some(rate),
+                ..Settings::default()
+            };
+            assert!(settings.check().is_err(), "{rate}");
+        }
+    }
+
+    #[test]
+    fn checks_keys() {
+        let keys = Settings {
+            keys: MAX_KEYS + 1,
+            ..Settings::default()
+        };
+        assert!(keys.check().is_err());
+
+        for zipf_theta in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            let settings = Settings {
+                zipf_theta,
+                ..Settings::default()
+            };
+            assert!(settings.check().is_err(), "{zipf_theta}");
+        }
+    }
 }
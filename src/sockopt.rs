@@ -0,0 +1,123 @@
+use std::{mem::size_of, net::SocketAddr, os::fd::RawFd, os::raw::c_void};
+
+use libc::{
+    c_int, linger, IPPROTO_IP, IPPROTO_IPV6, IPPROTO_TCP, IPV6_TCLASS, IP_TOS, SOL_SOCKET,
+    SO_BUSY_POLL, SO_LINGER, SO_RCVBUF, SO_SNDBUF, SO_TIMESTAMPING, TCP_NODELAY, TCP_QUICKACK,
+};
+use nix::errno::Errno;
+
+use crate::timestamps::TIMESTAMPING_FLAGS;
+
+/// Socket options as given on the command line
+#[derive(Clone, Debug, Default)]
+pub struct SocketOptions {
+    pub nodelay: Option<bool>,
+    pub send_buf: Option<c_int>,
+    pub recv_buf: Option<c_int>,
+    pub busy_poll: Option<c_int>,
+    pub quickack: Option<bool>,
+    pub linger: Option<c_int>,
+    pub tos: Option<c_int>,
+    pub timestamping: bool,
+}
+
+impl SocketOptions {
+    /// Turn parsed options into the list of setsockopt calls done on every new socket
+    pub fn resolve(&self, target: &SocketAddr) -> Vec<SockOpt> {
+        let mut out = Vec::new();
+
+        if let Some(v) = self.nodelay {
+            out.push(SockOpt::int(IPPROTO_TCP, TCP_NODELAY, v as c_int));
+        }
+        if let Some(v) = self.quickack {
+            out.push(SockOpt::int(IPPROTO_TCP, TCP_QUICKACK, v as c_int));
+        }
+        if let Some(v) = self.send_buf {
+            out.push(SockOpt::int(SOL_SOCKET, SO_SNDBUF, v));
+        }
+        if let Some(v) = self.recv_buf {
+            out.push(SockOpt::int(SOL_SOCKET, SO_RCVBUF, v));
+        }
+        if let Some(v) = self.busy_poll {
+            out.push(SockOpt::int(SOL_SOCKET, SO_BUSY_POLL, v));
+        }
+        if let Some(v) = self.linger {
+            out.push(SockOpt {
+                level: SOL_SOCKET,
+                name: SO_LINGER,
+                value: SockOptValue::Linger(linger {
+                    l_onoff: 1,
+                    l_linger: v,
+                }),
+            });
+        }
+        if let Some(v) = self.tos {
+            match target {
+                SocketAddr::V4(_) => out.push(SockOpt::int(IPPROTO_IP, IP_TOS, v)),
+                SocketAddr::V6(_) => out.push(SockOpt::int(IPPROTO_IPV6, IPV6_TCLASS, v)),
+            }
+        }
+
+        if self.timestamping {
+            out.push(SockOpt::int(
+                SOL_SOCKET,
+                SO_TIMESTAMPING,
+                TIMESTAMPING_FLAGS,
+            ));
+        }
+
+        out
+    }
+
+    /// TCP_QUICKACK to set again before every read, kernel leaves quickack mode on its own
+    pub fn quickack(&self) -> Option<SockOpt> {
+        self.quickack
+            .map(|v| SockOpt::int(IPPROTO_TCP, TCP_QUICKACK, v as c_int))
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum SockOptValue {
+    Int(c_int),
+    Linger(linger),
+}
+
+#[derive(Clone, Debug)]
+pub struct SockOpt {
+    pub level: c_int,
+    pub name: c_int,
+    pub value: SockOptValue,
+}
+
+impl SockOpt {
+    fn int(level: c_int, name: c_int, value: c_int) -> Self {
+        Self {
+            level,
+            name,
+            value: SockOptValue::Int(value),
+        }
+    }
+
+    /// Pointer and length of the value in the form setsockopt expects
+    ///
+    /// The pointer stays valid for as long as self does, kernel only reads from it
+    pub fn raw(&self) -> (*mut c_void, c_int) {
+        match &self.value {
+            SockOptValue::Int(v) => (
+                v as *const c_int as *mut c_void,
+                size_of::<c_int>() as c_int,
+            ),
+            SockOptValue::Linger(v) => (
+                v as *const linger as *mut c_void,
+                size_of::<linger>() as c_int,
+            ),
+        }
+    }
+
+    /// Fallback for kernels that can not do setsockopt through io_uring
+    pub fn set_blocking(&self, fd: RawFd) -> Result<(), Errno> {
+        let (ptr, len) = self.raw();
+        let out = unsafe { libc::setsockopt(fd, self.level, self.name, ptr, len as u32) };
+        Errno::result(out).map(drop)
+    }
+}
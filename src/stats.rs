@@ -1,14 +1,70 @@
-use std::{time::Duration};
-use crate::settings::get_settings;
+use crate::{grpc, histogram::Histogram, sequence::Arrival, settings::Settings, validate::Failure};
+use std::{fmt, time::Duration};
 
 #[derive(Debug)]
 pub struct Statistics {
     failed_connections: u64,
     wrong_return: u64,
+    /// Wrong results split by what gave them away, indexed by `Failure`
+    failures: [u64; Failure::ALL.len()],
     successful_returns: u64,
+    timeouts: u64,
+    /// UDP only, retransmissions included
+    datagrams_sent: u64,
+    datagrams_answered: u64,
+    duplicates: u64,
+    /// Responses that came after a newer attempt was sent
+    reordered: u64,
+    /// Key/value lookups that found their key
+    hits: u64,
+    misses: u64,
+    /// HTTP/2 streams the server reset and GOAWAY frames it sent
+    rst_streams: u64,
+    goaways: u64,
+    /// TLS handshakes that did not get through, connection is retried after
+    tls_failures: u64,
+    /// Calls that ended with given gRPC status, OK ones are not counted
+    grpc_statuses: [u64; grpc::STATUSES],
     min_nanos: u128,
     max_nanos: u128,
     nanos_spent: u128,
+    /// Running mean and sum of squared differences from it (Welford)
+    mean_nanos: f64,
+    m2_nanos: f64,
+    measurements: u64,
+    latency: Histogram,
+    /// Time between kernel sending request and receiving response
+    kernel_rtt: Histogram,
+    /// Time servers reported in response header
+    server_time: Histogram,
+    /// From connect to kernel having TLS keys
+    tls_handshake: Histogram,
+    /// Length of the measured window
+    elapsed: Duration,
+    /// Sum of per worker throughput, each over its own window
+    good_per_second: f64,
+    /// Time it took to get all connections up before measuring started, None when nothing
+    /// waited for them (ramp up)
+    setup: Option<Duration>,
+    breakdown: Breakdown,
+}
+
+/// Per thread and per connection numbers, kept aside as merging would hide imbalance
+#[derive(Default)]
+struct Breakdown {
+    /// Good pongs per second of every worker thread
+    threads: Vec<f64>,
+    /// Right results of every connection, in thread order
+    connections: Vec<u64>,
+}
+
+impl fmt::Debug for Breakdown {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Breakdown")
+            .field("threads", &self.threads)
+            .field("connections", &self.connections.len())
+            .finish()
+    }
 }
 
 impl Default for Statistics {
@@ -16,10 +72,33 @@ impl Default for Statistics {
         Self {
             failed_connections: Default::default(),
             wrong_return: Default::default(),
+            failures: Default::default(),
             successful_returns: Default::default(),
+            timeouts: Default::default(),
+            datagrams_sent: Default::default(),
+            datagrams_answered: Default::default(),
+            duplicates: Default::default(),
+            reordered: Default::default(),
+            hits: Default::default(),
+            misses: Default::default(),
+            rst_streams: Default::default(),
+            goaways: Default::default(),
+            tls_failures: Default::default(),
+            grpc_statuses: Default::default(),
             min_nanos: u128::MAX,
             max_nanos: Default::default(),
             nanos_spent: Default::default(),
+            mean_nanos: Default::default(),
+            m2_nanos: Default::default(),
+            measurements: Default::default(),
+            latency: Default::default(),
+            kernel_rtt: Default::default(),
+            server_time: Default::default(),
+            tls_handshake: Default::default(),
+            elapsed: Default::default(),
+            good_per_second: Default::default(),
+            setup: Default::default(),
+            breakdown: Default::default(),
         }
     }
 }
@@ -29,21 +108,116 @@ impl Statistics {
         self.failed_connections += other.failed_connections;
         self.successful_returns += other.successful_returns;
         self.wrong_return += other.wrong_return;
+        for (ours, theirs) in self.failures.iter_mut().zip(other.failures) {
+            *ours += theirs;
+        }
+        self.timeouts += other.timeouts;
+        self.datagrams_sent += other.datagrams_sent;
+        self.datagrams_answered += other.datagrams_answered;
+        self.duplicates += other.duplicates;
+        self.reordered += other.reordered;
+        self.hits += other.hits;
+        self.misses += other.misses;
+        self.rst_streams += other.rst_streams;
+        self.goaways += other.goaways;
+        self.tls_failures += other.tls_failures;
+        for (ours, theirs) in self.grpc_statuses.iter_mut().zip(other.grpc_statuses) {
+            *ours += theirs;
+        }
         self.max_nanos = self.max_nanos.max(other.max_nanos);
         self.min_nanos = self.min_nanos.min(other.min_nanos);
         self.nanos_spent += other.nanos_spent;
+
+        // Chan et al. pairwise update, keeps merged variance exact
+        let count = self.measurements + other.measurements;
+        if count > 0 {
+            let delta = other.mean_nanos - self.mean_nanos;
+            let ours = self.measurements as f64;
+            let theirs = other.measurements as f64;
+            self.mean_nanos += delta * theirs / count as f64;
+            self.m2_nanos += other.m2_nanos + delta * delta * ours * theirs / count as f64;
+            self.measurements = count;
+        }
+
+        self.latency.merge(&other.latency);
+        self.kernel_rtt.merge(&other.kernel_rtt);
+        self.server_time.merge(&other.server_time);
+        self.tls_handshake.merge(&other.tls_handshake);
+        self.elapsed = self.elapsed.max(other.elapsed);
+        self.good_per_second += other.good_per_second;
+        self.setup = self.setup.max(other.setup);
+        self.breakdown.threads.extend(other.breakdown.threads);
+        self.breakdown
+            .connections
+            .extend(other.breakdown.connections);
+    }
+
+    /// Forget everything measured so far, connection failures and handshakes are kept
+    pub fn reset_measurements(&mut self) {
+        *self = Self {
+            failed_connections: self.failed_connections,
+            tls_failures: self.tls_failures,
+            tls_handshake: std::mem::take(&mut self.tls_handshake),
+            setup: self.setup,
+            ..Self::default()
+        };
     }
 
     pub fn increment_connect_fail(&mut self) {
         self.failed_connections += 1;
     }
 
-    pub fn increment_wrong_returns(&mut self) {
+    pub fn increment_wrong_returns(&mut self, failure: Failure) {
         self.wrong_return += 1;
+        self.failures[failure as usize] += 1;
+    }
+
+    pub fn add_successful_returns(&mut self, count: u64) {
+        self.successful_returns += count;
+    }
+
+    pub fn increment_timeouts(&mut self) {
+        self.timeouts += 1;
     }
 
-    pub fn increment_successful_returns(&mut self) {
-        self.successful_returns += 1;
+    pub fn increment_hits(&mut self) {
+        self.hits += 1;
+    }
+
+    pub fn increment_misses(&mut self) {
+        self.misses += 1;
+    }
+
+    pub fn increment_rst_streams(&mut self) {
+        self.rst_streams += 1;
+    }
+
+    pub fn increment_goaways(&mut self) {
+        self.goaways += 1;
+    }
+
+    pub fn increment_tls_failures(&mut self) {
+        self.tls_failures += 1;
+    }
+
+    pub fn increment_grpc_status(&mut self, status: usize) {
+        self.grpc_statuses[status] += 1;
+    }
+
+    pub fn increment_datagrams_sent(&mut self) {
+        self.datagrams_sent += 1;
+    }
+
+    pub fn new_arrival(&mut self, arrival: Arrival) {
+        match arrival {
+            Arrival::Fresh => self.datagrams_answered += 1,
+            Arrival::Earlier | Arrival::Stale => {
+                self.datagrams_answered += 1;
+                self.reordered += 1;
+            }
+            Arrival::Duplicate => self.duplicates += 1,
+            Arrival::Bogus => {}
+        }
     }
 
     pub fn new_measurement(&mut self, duration: Duration) {
@@ -51,11 +225,110 @@ impl Statistics {
         self.min_nanos = self.min_nanos.min(nanos);
         self.max_nanos = self.max_nanos.max(nanos);
         self.nanos_spent += nanos;
+        self.latency.record(nanos as u64);
+
+        self.measurements += 1;
+        let delta = nanos as f64 - self.mean_nanos;
+        self.mean_nanos += delta / self.measurements as f64;
+        self.m2_nanos += delta * (nanos as f64 - self.mean_nanos);
+    }
+
+    pub fn new_kernel_measurement(&mut self, duration: Duration) {
+        self.kernel_rtt.record(duration.as_nanos() as u64);
+    }
+
+    pub fn new_server_measurement(&mut self, duration: Duration) {
+        self.server_time.record(duration.as_nanos() as u64);
+    }
+
+    pub fn new_tls_handshake(&mut self, duration: Duration) {
+        self.tls_handshake.record(duration.as_nanos() as u64);
+    }
+
+    pub fn mean_nanos(&self) -> f64 {
+        self.mean_nanos
+    }
+
+    /// Sample standard deviation of latency
+    pub fn stddev_nanos(&self) -> f64 {
+        if self.measurements < 2 {
+            return 0.0;
+        }
+        (self.m2_nanos / (self.measurements - 1) as f64).sqrt()
+    }
+
+    /// Close the measured window of a worker, throughput is computed from its real length
+    pub fn set_elapsed(&mut self, elapsed: Duration) {
+        self.elapsed = elapsed;
+        if !elapsed.is_zero() {
+            self.good_per_second = self.successful_returns as f64 / elapsed.as_secs_f64();
+        }
+        self.breakdown.threads = vec![self.good_per_second];
+    }
+
+    pub fn set_connections(&mut self, requests: Vec<u64>) {
+        self.breakdown.connections = requests;
+    }
+
+    pub fn set_setup(&mut self, setup: Duration) {
+        self.setup = Some(setup);
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    pub fn good_per_second(&self) -> f64 {
+        self.good_per_second
+    }
+
+    pub fn successful_returns(&self) -> u64 {
+        self.successful_returns
+    }
+
+    /// Exchanges that got an answer but not the right one
+    pub fn wrong_returns(&self) -> u64 {
+        self.wrong_return
+    }
+
+    /// Wrong results given away by given kind of failure
+    pub fn wrong_returns_of(&self, failure: Failure) -> u64 {
+        self.failures[failure as usize]
+    }
+
+    pub fn failed_connections(&self) -> u64 {
+        self.failed_connections
+    }
+
+    pub fn timeouts(&self) -> u64 {
+        self.timeouts
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+
+    pub fn tls_failures(&self) -> u64 {
+        self.tls_failures
+    }
+
+    /// Exchanges that did not end with a right result
+    pub fn errors(&self) -> u64 {
+        self.wrong_return + self.timeouts
+    }
+
+    /// Latency in nanoseconds under which given quantile (0..=1) of responses arrived
+    pub fn latency_at(&self, quantile: f64) -> u64 {
+        self.latency.value_at_quantile(quantile)
     }
 }
 
-pub fn print_stats_final(stats: &Statistics) {
-    let settings = get_settings();
+/// Report of a finished run, settings are the ones it ran with
+pub fn print_stats_final(stats: &Statistics, settings: &Settings) {
     println!("BENCHMARK ENDED");
     println!("Failed to connected in {} cases", stats.failed_connections);
     println!(
@@ -67,21 +340,238 @@ pub fn print_stats_final(stats: &Statistics) {
         stats.successful_returns
     );
 
-    println!(
-        "Average good pongs per second: {}",
-        stats.successful_returns / settings.burn_time.tv_sec as u64
-    );
+    println!("Requests timed out in {} cases", stats.timeouts);
+
+    if stats.wrong_return > 0 {
+        let kinds: Vec<String> = Failure::ALL
+            .iter()
+            .map(|failure| format!("{} {}", failure.name(), stats.failures[*failure as usize]))
+            .collect();
+        println!("Wrong results by kind: {}", kinds.join(", "));
+    }
+
+    let lookups = stats.hits + stats.misses;
+    if lookups > 0 {
+        println!(
+            "Lookups hit {} missed {} (hit ratio {:.2}% with {:?} keys)",
+            stats.hits,
+            stats.misses,
+            stats.hits as f64 * 100.0 / lookups as f64,
+            settings.key_distribution
+        );
+    }
+
+    if stats.datagrams_sent > 0 {
+        let lost = stats
+            .datagrams_sent
+            .saturating_sub(stats.datagrams_answered);
+        println!(
+            "Datagrams lost {} of {} ({:.3}%), {} duplicated, {} reordered",
+            lost,
+            stats.datagrams_sent,
+            lost as f64 * 100.0 / stats.datagrams_sent as f64,
+            stats.duplicates,
+            stats.reordered
+        );
+    }
 
+    if stats.tls_handshake.total() > 0 || stats.tls_failures > 0 {
+        println!("TLS handshakes failed in {} cases", stats.tls_failures);
+        for (name, quantile) in [("p50", 0.5), ("p90", 0.9), ("p99", 0.99)] {
+            println!(
+                "TLS handshake {name} {}",
+                format_nanos(stats.tls_handshake.value_at_quantile(quantile) as f64)
+            );
+        }
+    }
+
+    if let Some(setup) = stats.setup {
+        println!(
+            "Connection setup took {}",
+            format_nanos(setup.as_nanos() as f64)
+        );
+    }
     println!(
-        "Maximum time spent {}ms ({}ns)",
-        Duration::from_nanos(stats.max_nanos as u64).as_millis(), stats.max_nanos
+        "Measured for {}",
+        format_nanos(stats.elapsed.as_nanos() as f64)
     );
 
     println!(
-        "Minimum time spent {}ms ({}ns)",
-        Duration::from_nanos(stats.min_nanos as u64).as_millis(), stats.min_nanos
+        "Average good pongs per second: {}",
+        stats.good_per_second as u64
     );
 
+    if stats.measurements == 0 {
+        println!("No latency has been measured");
+    } else {
+        println!(
+            "Latency mean {} stddev {}",
+            format_nanos(stats.mean_nanos),
+            format_nanos(stats.stddev_nanos())
+        );
+        println!(
+            "Latency min {} max {}",
+            format_nanos(stats.min_nanos as f64),
+            format_nanos(stats.max_nanos as f64)
+        );
+
+        for (name, quantile) in [("p50", 0.5), ("p90", 0.9), ("p99", 0.99), ("p99.9", 0.999)] {
+            println!(
+                "Latency {name} {}",
+                format_nanos(stats.latency_at(quantile) as f64)
+            );
+        }
+    }
+
+    if stats.kernel_rtt.total() > 0 {
+        for (name, quantile) in [("p50", 0.5), ("p90", 0.9), ("p99", 0.99)] {
+            let kernel = stats.kernel_rtt.value_at_quantile(quantile);
+            let user = stats.latency_at(quantile);
+            println!(
+                "Kernel RTT {name} {} (client side adds {})",
+                format_nanos(kernel as f64),
+                format_nanos(user.saturating_sub(kernel) as f64)
+            );
+        }
+    }
+
+    if stats.server_time.total() > 0 {
+        for (name, quantile) in [("p50", 0.5), ("p90", 0.9), ("p99", 0.99)] {
+            println!(
+                "Server reported time {name} {}",
+                format_nanos(stats.server_time.value_at_quantile(quantile) as f64)
+            );
+        }
+    }
+
+    if stats.rst_streams + stats.goaways > 0 {
+        println!(
+            "Streams reset {} times, connections sent away {} times",
+            stats.rst_streams, stats.goaways
+        );
+    }
+
+    let calls: Vec<String> = stats
+        .grpc_statuses
+        .iter()
+        .enumerate()
+        .filter(|(_, &count)| count > 0)
+        .map(|(status, count)| format!("{} {count}", grpc::status_name(status)))
+        .collect();
+    if !calls.is_empty() {
+        println!("Failed gRPC calls by status: {}", calls.join(", "));
+    }
+
+    print_fairness(stats, settings);
+
     println!("RAW PRINT");
     println!("{stats:#?}");
 }
+
+/// Format nanoseconds in whichever of ns, µs, ms or s reads best
+pub fn format_nanos(nanos: f64) -> String {
+    if nanos < 1e3 {
+        format!("{nanos:.0}ns")
+    } else if nanos < 1e6 {
+        format!("{:.2}µs", nanos / 1e3)
+    } else if nanos < 1e9 {
+        format!("{:.2}ms", nanos / 1e6)
+    } else {
+        format!("{:.2}s", nanos / 1e9)
+    }
+}
+
+fn print_fairness(stats: &Statistics, settings: &Settings) {
+    let threads = &stats.breakdown.threads;
+    for (index, per_second) in threads.iter().enumerate() {
+        println!(
+            "Thread {index} good pongs per second: {}",
+            *per_second as u64
+        );
+    }
+
+    let connections = &stats.breakdown.connections;
+    if connections.is_empty() {
+        return;
+    }
+
+    let mut sorted = connections.clone();
+    sorted.sort_unstable();
+    println!(
+        "Right results per connection min {} median {} max {}",
+        sorted[0],
+        sorted[sorted.len() / 2],
+        sorted[sorted.len() - 1]
+    );
+
+    if let Some(index) = jain_index(connections) {
+        println!("Jain's fairness index {index:.4}");
+    }
+
+    if settings.connections_table {
+        println!("CONNECTIONS");
+        println!("{:>10} {:>12}", "connection", "right");
+        for (index, requests) in connections.iter().enumerate() {
+            println!("{index:>10} {requests:>12}");
+        }
+    }
+}
+
+/// Jain's index, 1 means every share is the same and 1/n means one got all, None if all are 0
+fn jain_index(shares: &[u64]) -> Option<f64> {
+    let sum: f64 = shares.iter().map(|&x| x as f64).sum();
+    let squares: f64 = shares.iter().map(|&x| (x as f64) * (x as f64)).sum();
+    (squares > 0.0).then(|| sum * sum / (shares.len() as f64 * squares))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merged_mean_and_stddev_match_single_pass() {
+        let latencies: Vec<Duration> = (1..=1000)
+            .map(|x| Duration::from_nanos(x * x % 7919 + 10_000))
+            .collect();
+        let mut single = Statistics::default();
+        for &latency in &latencies {
+            single.new_measurement(latency);
+        }
+
+        // Uneven shares and a worker that measured nothing
+        let mut merged = Statistics::default();
+        for part in [
+            &latencies[..10],
+            &latencies[10..700],
+            &[],
+            &latencies[700..],
+        ] {
+            let mut worker = Statistics::default();
+            for &latency in part {
+                worker.new_measurement(latency);
+            }
+            merged.merge(worker);
+        }
+
+        let close = |a: f64, b: f64| (a - b).abs() <= b * 1e-9;
+        assert!(close(merged.mean_nanos(), single.mean_nanos()));
+        assert!(close(merged.stddev_nanos(), single.stddev_nanos()));
+
+        let n = latencies.len() as f64;
+        let mean = latencies.iter().map(|x| x.as_nanos() as f64).sum::<f64>() / n;
+        let squares: f64 = latencies
+            .iter()
+            .map(|x| (x.as_nanos() as f64 - mean).powi(2))
+            .sum();
+        assert!(close(single.mean_nanos(), mean));
+        assert!(close(single.stddev_nanos(), (squares / (n - 1.0)).sqrt()));
+    }
+
+    #[test]
+    fn jain_index_bounds() {
+        assert_eq!(jain_index(&[5, 5, 5, 5]), Some(1.0));
+        assert_eq!(jain_index(&[8, 0, 0, 0]), Some(0.25));
+        assert_eq!(jain_index(&[1, 3]), Some(0.8));
+        assert_eq!(jain_index(&[0, 0]), None);
+    }
+}
@@ -0,0 +1,43 @@
+use std::{
+    os::{fd::RawFd, raw::c_void},
+    sync::atomic::{AtomicBool, AtomicI32, Ordering},
+};
+
+use libc::{c_int, EFD_CLOEXEC, SIGINT, SIG_DFL};
+
+/// Eventfd that becomes readable once the user asked us to stop
+static STOP_FD: AtomicI32 = AtomicI32::new(-1);
+static STOPPED: AtomicBool = AtomicBool::new(false);
+
+/// Catch Ctrl-C so workers can end the burn early and results still get printed
+///
+/// Second Ctrl-C kills the process as usual
+pub fn install() {
+    let fd = unsafe { libc::eventfd(0, EFD_CLOEXEC) };
+    assert!(fd >= 0, "Could not create eventfd");
+    STOP_FD.store(fd, Ordering::Release);
+
+    unsafe {
+        libc::signal(SIGINT, on_signal as *const () as libc::sighandler_t);
+    }
+}
+
+/// Poll this for readability in every ring
+pub fn fd() -> RawFd {
+    STOP_FD.load(Ordering::Acquire)
+}
+
+pub fn requested() -> bool {
+    STOPPED.load(Ordering::Acquire)
+}
+
+extern "C" fn on_signal(_: c_int) {
+    STOPPED.store(true, Ordering::Release);
+
+    // Only async signal safe calls from here on
+    let one: u64 = 1;
+    unsafe {
+        libc::write(fd(), &one as *const u64 as *const c_void, size_of::<u64>());
+        libc::signal(SIGINT, SIG_DFL);
+    }
+}
@@ -2,22 +2,33 @@ use std::{
     net::SocketAddr,
     os::{fd::RawFd, raw::c_void},
     pin::Pin,
-    time::Instant,
+    sync::Arc,
+    time::{Duration, Instant},
 };
 
 use libc::{in6_addr, in_addr, sockaddr_in, sockaddr_in6, AF_INET};
 use liburing_rs::*;
 use nix::errno::Errno;
+use rustls::{ClientConfig, ClientConnection};
 
 use crate::{
+    header::{RequestHeader, HEADER_LEN},
+    sequence::{Arrival, SequenceTracker, SEQUENCE_LEN},
     settings::{get_settings, Protocol},
+    sockopt::SockOpt,
     stats::Statistics,
+    timestamps::KernelStamps,
+    tls,
     uring::ThreadIo,
+    validate::{Failure, Validation},
+    workload::{Frame, Workload},
 };
 
-pub struct ThreadLocalTasking {
-    tasks: Box<[Task]>,
+pub struct ThreadLocalTasking<W: Workload> {
+    tasks: Box<[Task<W>]>,
     memory: memmap2::MmapMut,
+    /// Tasks that got through connect and wait on start gate
+    connected: usize,
 }
 
 struct TaskBuf<'a> {
@@ -25,14 +36,39 @@ struct TaskBuf<'a> {
     receive: &'a mut [u8],
 }
 
-const TASK_BUF: usize = 2 * 4096;
+/// Bytes of the send buffer and of the receive buffer every task has, requests have to fit
+pub const REQUEST_BUF: usize = 4096;
+const TASK_BUF: usize = 2 * REQUEST_BUF;
 
-impl ThreadLocalTasking {
-    pub fn setup(io: &mut ThreadIo, stats: &mut Statistics) -> ThreadLocalTasking {
+/// Set on user data of linked timeouts and quickack re-arms, their CQEs carry nothing the
+/// task needs
+const SIDE_FLAG: u64 = 1 << 62;
+
+impl<W: Workload> ThreadLocalTasking<W> {
+    pub fn setup(
+        io: &mut ThreadIo,
+        stats: &mut Statistics,
+        rate: Option<f64>,
+        gate: Option<RawFd>,
+        thread: usize,
+        workload: &W,
+    ) -> ThreadLocalTasking<W> {
         let now = Instant::now();
         let settings = get_settings();
         let connections = settings.connections_per_thread();
 
+        assert!(
+            W::ECHO || !settings.header,
+            "Request header needs a workload that echoes requests back"
+        );
+
+        // Every connection gets an equal share of the rate
+        let interval = rate.map(|rate| {
+            Duration::from_secs_f64((connections * settings.threads.get()) as f64 / rate)
+        });
+
+        let tls_config = settings.tls.is_some().then(tls::client_config);
+
         let len = connections * TASK_BUF;
         let mut mapped = memmap2::MmapMut::map_anon(len).expect("Could not map memory");
 
@@ -54,26 +90,63 @@ impl ThreadLocalTasking {
 
             let mut tasks = Vec::with_capacity(connections);
             for i in 0..connections {
+                let connection = (thread * connections + i) as u64;
                 tasks.push(Task {
                     index: i,
+                    connection,
+                    sent: 0,
+                    workload: workload.for_connection(connection),
+                    request_len: 0,
+                    received: 0,
                     fd: 0,
-                    dumb_rand: i as u64,
+                    requests: 0,
                     state: TaskState::default(),
                     addr: None,
                     addr6: None,
                     send_time: None,
+                    gate,
+                    tls_config: tls_config.clone(),
+                    tls: None,
+                    stamps: settings.kernel_timestamps.then(KernelStamps::new),
+                    sequence: (W::ECHO && matches!(settings.proto, Protocol::Udp))
+                        .then(SequenceTracker::default),
+                    pace: interval.map(|interval| Pace {
+                        interval,
+                        next: now,
+                        wait: __kernel_timespec::default(),
+                    }),
                 });
             }
 
+            // With ramp up each task waits on its own timeout before creating a socket,
+            // expiry of it lands in TaskState::NewSock same as a direct start would
+            let delays: Vec<__kernel_timespec> = match settings.ramp {
+                Some(ramp) => (0..connections)
+                    .map(|i| __kernel_timespec::from(ramp * i as u32 / connections as u32))
+                    .collect(),
+                None => Vec::new(),
+            };
+
             for ele in tasks.iter_mut() {
                 let index = ele.index;
+                if let Some(delay) = delays.get(index) {
+                    let sqe = io.push();
+                    io_uring_prep_timeout(sqe, delay, 0, 0);
+                    io_uring_sqe_set_data64(sqe, index as u64);
+                    continue;
+                }
+
                 let mut buf = buffers_for_task(&mut mapped, index);
                 ele.progress(None, io, &mut buf, stats, &now);
             }
 
-            ThreadLocalTasking {
+            // Kernel copies timeouts on submit, delays can not be dropped before that
+            io.submit();
+
+            ThreadLocalTasking::<W> {
                 tasks: tasks.into_boxed_slice(),
                 memory: mapped,
+                connected: 0,
             }
         }
     }
@@ -85,42 +158,106 @@ impl ThreadLocalTasking {
         stats: &mut Statistics,
         now: &Instant,
     ) {
+        if cqe.user_data & SIDE_FLAG != 0 {
+            return;
+        }
+
         let index = cqe.user_data as usize;
         let mut buf = buffers_for_task(&mut self.memory, index);
-        self.tasks[index].progress(Some(cqe), io, &mut buf, stats, now);
+        let task = &mut self.tasks[index];
+        task.progress(Some(cqe), io, &mut buf, stats, now);
+
+        if let TaskState::Gated = task.state {
+            self.connected += 1;
+        }
+    }
+
+    pub fn requests_per_connection(&self) -> Vec<u64> {
+        self.tasks.iter().map(|task| task.requests).collect()
+    }
+
+    pub fn reset_requests(&mut self) {
+        for task in self.tasks.iter_mut() {
+            task.requests = 0;
+        }
+    }
+
+    pub fn all_connected(&self) -> bool {
+        self.connected >= self.tasks.len()
     }
 }
 
 fn buffers_for_task<'a>(map: &'a mut memmap2::MmapMut, index: usize) -> TaskBuf<'a> {
     let array = &mut map[(index * TASK_BUF)..(index * TASK_BUF + TASK_BUF)];
-    let (send, receive) = array.split_at_mut(4096);
+    let (send, receive) = array.split_at_mut(REQUEST_BUF);
     TaskBuf { send, receive }
 }
 
-struct Task {
+struct Task<W: Workload> {
     index: usize,
+    /// Connection id carried in request header
+    connection: u64,
+    /// Requests built so far, sequence number of header when there is no tracker
+    sent: u64,
+    workload: W,
+    /// Bytes of request in flight, header and sequence number included
+    request_len: usize,
+    /// Bytes of response read so far
+    received: usize,
     fd: RawFd,
-    dumb_rand: u64,
     state: TaskState,
+    /// Right results this connection got back
+    requests: u64,
 
     send_time: Option<Instant>,
+    /// Eventfd polled before first request, see worker::StartGate
+    gate: Option<RawFd>,
+    tls_config: Option<Arc<ClientConfig>>,
+    /// Session whose handshake is under way and when it started
+    tls: Option<(Box<ClientConnection>, Instant)>,
+    stamps: Option<Box<KernelStamps>>,
+    /// Only UDP needs these, stream sockets do not lose or reorder
+    sequence: Option<SequenceTracker>,
+    pace: Option<Pace>,
 
     //Adresses
     addr: Option<Pin<Box<sockaddr_in>>>,
     addr6: Option<Pin<Box<sockaddr_in6>>>,
 }
 
+/// Keeps a task to its share of the offered rate
+struct Pace {
+    interval: Duration,
+    /// When next request is due to be sent
+    next: Instant,
+    wait: __kernel_timespec,
+}
+
 #[derive(Default, Debug)]
 pub enum TaskState {
     #[default]
     NewSock,
     Connect,
+    /// Waiting on setsockopt for the option before this index
+    Configure(usize),
     Setup,
+    /// Waiting for TLS handshake bytes to be sent
+    TlsSend,
+    /// Waiting for TLS handshake bytes from the server
+    TlsReceive,
+    /// Waiting for workload handshake to be sent
+    Handshake,
+    /// Waiting for response to the handshake
+    HandshakeReply,
+    /// Connected and waiting for every other task to get there too
+    Gated,
     Send,
+    /// Waiting for next request to be due
+    Paced,
     Receive,
 }
 
-impl Task {
+impl<W: Workload> Task<W> {
     pub fn progress(
         &mut self,
         cqe: Option<io_uring_cqe>,
@@ -136,6 +273,12 @@ impl Task {
                 }
             }
 
+            if matches!(self.state, TaskState::Receive | TaskState::Send)
+                && get_settings().timeout.is_some()
+            {
+                ring.reserve(2);
+            }
+
             let sqe = ring.push();
             io_uring_sqe_set_data64(sqe, self.index as u64);
 
@@ -168,9 +311,36 @@ impl Task {
 
                     self.fd = cqe.res;
 
-                    self.make_connect(sqe);
+                    let settings = get_settings();
+                    if settings.sock_opts.is_empty() {
+                        self.make_connect(sqe);
+                        self.state = TaskState::Setup;
+                    } else {
+                        self.make_sock_opt(sqe, 0);
+                        self.state = TaskState::Configure(1);
+                    }
+                }
+                TaskState::Configure(next) => {
+                    let Some(cqe) = cqe else {
+                        panic!("Invalid state")
+                    };
+
+                    let settings = get_settings();
+                    if cqe.res < 0 {
+                        // Older kernels do not know socket commands, retry with a plain syscall
+                        let opt = &settings.sock_opts[next - 1];
+                        if let Err(err) = opt.set_blocking(self.fd) {
+                            panic!("Could not set socket option {opt:?} {err}");
+                        }
+                    }
 
-                    self.state = TaskState::Setup;
+                    if next < settings.sock_opts.len() {
+                        self.make_sock_opt(sqe, next);
+                        self.state = TaskState::Configure(next + 1);
+                    } else {
+                        self.make_connect(sqe);
+                        self.state = TaskState::Setup;
+                    }
                 }
                 TaskState::Setup => {
                     let Some(cqe) = cqe else {
@@ -186,62 +356,274 @@ impl Task {
                     self.addr = None;
                     self.addr6 = None;
 
-                    let out: &mut [u64] = bytemuck::cast_slice_mut(buf.send);
-                    for (index, ele) in out.iter_mut().enumerate() {
-                        self.dumb_rand = self.dumb_rand.wrapping_add(*ele);
-                        *ele ^= self.dumb_rand + (index as u64);
+                    if let Some(config) = self.tls_config.as_ref() {
+                        self.tls = Some((Box::new(tls::connect(config)), *now));
+                        self.tls_step(sqe, buf, stats, now);
+                        return;
                     }
 
-                    io_uring_prep_send_zc(
-                        sqe,
-                        self.fd,
-                        buf.send.as_ptr() as *const c_void,
-                        buf.send.len(),
-                        0,
-                        0,
-                    );
+                    self.connected(sqe, buf, stats, now);
+                }
+                TaskState::TlsSend => {
+                    let Some(cqe) = cqe else {
+                        panic!("Invalid state")
+                    };
 
-                    self.send_time = Some(*now);
+                    if cqe.res < 0 {
+                        self.tls_failed(sqe, stats);
+                        return;
+                    }
 
-                    self.state = TaskState::Receive;
+                    self.tls_step(sqe, buf, stats, now);
+                }
+                TaskState::TlsReceive => {
+                    let Some(cqe) = cqe else {
+                        panic!("Invalid state")
+                    };
+                    let Some((session, _)) = self.tls.as_mut() else {
+                        panic!("Invalid state")
+                    };
+
+                    if cqe.res <= 0 {
+                        self.tls_failed(sqe, stats);
+                        return;
+                    }
+
+                    let mut data = &buf.receive[..cqe.res as usize];
+                    while !data.is_empty() {
+                        let broken = match session.read_tls(&mut data) {
+                            Ok(_) => session.process_new_packets().is_err(),
+                            Err(_) => true,
+                        };
+                        if broken {
+                            self.tls_failed(sqe, stats);
+                            return;
+                        }
+                    }
+
+                    self.tls_step(sqe, buf, stats, now);
+                }
+                TaskState::Handshake => {
+                    let Some(cqe) = cqe else {
+                        panic!("Invalid state")
+                    };
+
+                    if cqe.res < 0 {
+                        stats.increment_connect_fail();
+                        self.make_reconnect(sqe);
+                        return;
+                    }
+
+                    self.make_handshake_read(sqe, buf);
+                }
+                TaskState::HandshakeReply => {
+                    let Some(cqe) = cqe else {
+                        panic!("Invalid state")
+                    };
+
+                    if cqe.res > 0 {
+                        self.received += cqe.res as usize;
+                        match self.workload.handshake_frame(&buf.receive[..self.received]) {
+                            Frame::Complete(len) => {
+                                self.workload.handshake_done(&buf.receive[..len]);
+                                self.received = 0;
+                                self.ready(sqe, buf, stats, now);
+                                return;
+                            }
+                            Frame::Incomplete if self.received < buf.receive.len() => {
+                                self.make_handshake_read(sqe, buf);
+                                return;
+                            }
+                            _ => {}
+                        }
+                    }
+
+                    // Server closed or turned us down
+                    stats.increment_connect_fail();
+                    self.make_reconnect(sqe);
+                }
+                TaskState::Gated => {
+                    self.send_first(sqe, buf, stats, now);
                 }
                 TaskState::Send => {
                     let Some(cqe) = cqe else {
                         panic!("Invalid state")
                     };
 
+                    if cqe.res == -(Errno::ECANCELED as i32) {
+                        // Read got cut off by its linked timeout
+                        stats.increment_timeouts();
+                        self.received = 0;
+                        match get_settings().proto {
+                            Protocol::Udp => {
+                                self.stamp_sequence(buf, stats, false);
+                                self.make_send(sqe, buf);
+                                self.send_time = Some(*now);
+                                self.state = TaskState::Receive;
+                            }
+                            Protocol::Tcp => {
+                                self.send_time = None;
+                                self.make_reconnect(sqe);
+                            }
+                        }
+                        return;
+                    }
+
                     if cqe.res < 0 {
                         eprintln!("Error whilst Receiving {}", Errno::from_raw(-cqe.res));
                     }
 
-                    if buf.receive != buf.send {
-                        stats.increment_wrong_returns();
-                    } else {
-                        stats.increment_successful_returns();
+                    // Nothing more is coming on a closed or broken connection, whatever
+                    // `--validate` says
+                    if cqe.res <= 0 {
+                        stats.increment_wrong_returns(Failure::Closed);
+                        self.send_time = None;
+                        self.make_reconnect(sqe);
+                        return;
+                    }
+
+                    self.received += cqe.res as usize;
+                    let mut verdict = Ok(());
+
+                    if let Some(tracker) = self.sequence.as_mut() {
+                        let arrival = if self.received < SEQUENCE_LEN {
+                            Arrival::Bogus
+                        } else {
+                            let mut seq = [0; SEQUENCE_LEN];
+                            seq.copy_from_slice(&buf.receive[..SEQUENCE_LEN]);
+                            tracker.arrived(u64::from_le_bytes(seq))
+                        };
+                        stats.new_arrival(arrival);
+
+                        match arrival {
+                            Arrival::Fresh | Arrival::Earlier => {}
+                            Arrival::Stale | Arrival::Duplicate => {
+                                // Not what we wait for, keep waiting
+                                self.received = 0;
+                                self.make_receive(sqe, ring, buf);
+                                return;
+                            }
+                            Arrival::Bogus => verdict = Err(Failure::Header),
+                        }
+                    }
+
+                    let mut rejected = false;
+                    // Bytes of this exchange, multiplexed workloads may read past it
+                    let mut consumed = self.received;
+                    if verdict.is_ok() {
+                        let received = &buf.receive[..self.received];
+                        self.workload.read(received, *now, stats);
+                        if W::MULTIPLEXED {
+                            // Room for what does not fit in at once, like big DATA frames
+                            let done = self.workload.drain();
+                            buf.receive.copy_within(done..self.received, 0);
+                            self.received -= done;
+                            consumed = self.received;
+                        }
+
+                        let request = &buf.send[..self.request_len];
+                        match self.workload.frame(request, &buf.receive[..self.received]) {
+                            Frame::Complete(len) if len == self.received => {
+                                self.workload.completed(&buf.receive[..len], stats)
+                            }
+                            Frame::Complete(len) if W::MULTIPLEXED && len < self.received => {
+                                self.workload.completed(&buf.receive[..len], stats);
+                                consumed = len;
+                            }
+                            Frame::Rejected(len) if len == self.received => {
+                                self.workload.completed(&buf.receive[..len], stats);
+                                rejected = true;
+                            }
+                            Frame::Incomplete
+                                if matches!(get_settings().proto, Protocol::Tcp)
+                                    && self.received < buf.receive.len() =>
+                            {
+                                // Rest of the response is still on its way
+                                self.make_receive(sqe, ring, buf);
+                                return;
+                            }
+                            Frame::Reconnect => {
+                                stats.increment_wrong_returns(Failure::Closed);
+                                self.send_time = None;
+                                self.make_reconnect(sqe);
+                                return;
+                            }
+                            framed if matches!(get_settings().proto, Protocol::Tcp) => {
+                                // Full buffer, bytes past the response or bytes that do not
+                                // parse, either way where the next response starts is lost
+                                let failure = match framed {
+                                    Frame::Invalid => Failure::Content,
+                                    _ => Failure::Length,
+                                };
+                                stats.increment_wrong_returns(failure);
+                                self.send_time = None;
+                                self.make_reconnect(sqe);
+                                return;
+                            }
+                            Frame::Invalid => verdict = Err(Failure::Content),
+                            _ => verdict = Err(Failure::Length),
+                        }
+                    }
+
+                    // Error replies are failures whatever `--validate` says
+                    let checked = match rejected {
+                        true => Err(Failure::Reply),
+                        false => self.validate(verdict, buf),
+                    };
+                    match checked {
+                        Ok(()) => {
+                            let count = self.workload.requests_per_exchange();
+                            stats.add_successful_returns(count);
+                            self.requests += count;
+                        }
+                        Err(failure) => stats.increment_wrong_returns(failure),
+                    }
+                    // Whatever is left is the start of the next exchange
+                    buf.receive.copy_within(consumed..self.received, 0);
+                    self.received -= consumed;
+
+                    if get_settings().header {
+                        let header = RequestHeader::read(buf.receive);
+                        if header.connection == self.connection && header.server_nanos != 0 {
+                            stats.new_server_measurement(Duration::from_nanos(header.server_nanos));
+                        }
                     }
 
-                    if let Some(started) = self.send_time.take() {
+                    if let Some(started) = self.send_time.take().filter(|_| !W::MULTIPLEXED) {
                         stats.new_measurement(now.duration_since(started));
                     }
 
-                    self.send_time = Some(*now);
+                    if let Some(stamps) = self.stamps.as_ref() {
+                        if let (Some(sent), Some(received)) =
+                            (stamps.sent(self.fd), stamps.received())
+                        {
+                            stats.new_kernel_measurement(received.saturating_sub(sent));
+                        }
+                    }
 
-                    let out: &mut [u64] = bytemuck::cast_slice_mut(buf.send);
-                    for ele in out.iter_mut() {
-                        self.dumb_rand = self.dumb_rand.wrapping_add(*ele);
-                        *ele ^= self.dumb_rand;
+                    let mut send_time = *now;
+                    if let Some(pace) = self.pace.as_mut() {
+                        pace.next += pace.interval;
+                        if pace.next > *now {
+                            pace.wait = __kernel_timespec::from(pace.next - *now);
+                            io_uring_prep_timeout(sqe, &pace.wait, 0, 0);
+                            self.state = TaskState::Paced;
+                            return;
+                        }
+
+                        // Running behind, latency is counted from when the request was due
+                        send_time = pace.next;
                     }
 
-                    io_uring_prep_send_zc(
-                        sqe,
-                        self.fd,
-                        buf.send.as_ptr() as *const c_void,
-                        buf.send.len(),
-                        0,
-                        0,
-                    );
+                    self.send_next(sqe, buf, stats, send_time);
+                }
+                TaskState::Paced => {
+                    let Some(pace) = self.pace.as_ref() else {
+                        panic!("Invalid state")
+                    };
 
-                    self.state = TaskState::Receive;
+                    let send_time = pace.next;
+                    self.send_next(sqe, buf, stats, send_time);
                 }
                 TaskState::Receive => {
                     let Some(cqe) = cqe else {
@@ -252,17 +634,299 @@ impl Task {
                         eprintln!("Error whilst Sending {}", Errno::from_raw(-cqe.res));
                     }
 
-                    io_uring_prep_read(
-                        sqe,
-                        self.fd,
-                        buf.receive.as_mut_ptr() as *mut c_void,
-                        buf.send.len() as u32,
-                        0,
-                    );
+                    self.make_receive(sqe, ring, buf);
+                }
+            }
+        }
+    }
+
+    /// Connection is up, send workload handshake if it has one
+    unsafe fn connected(
+        &mut self,
+        sqe: *mut io_uring_sqe,
+        buf: &mut TaskBuf<'_>,
+        stats: &mut Statistics,
+        now: &Instant,
+    ) {
+        unsafe {
+            self.request_len = self.workload.handshake(buf.send);
+            if self.request_len > 0 {
+                self.make_send(sqe, buf);
+                self.state = TaskState::Handshake;
+                return;
+            }
+
+            self.ready(sqe, buf, stats, now);
+        }
+    }
+
+    /// Move TLS handshake along, once it is done its keys go to the kernel
+    unsafe fn tls_step(
+        &mut self,
+        sqe: *mut io_uring_sqe,
+        buf: &mut TaskBuf<'_>,
+        stats: &mut Statistics,
+        now: &Instant,
+    ) {
+        unsafe {
+            let Some((session, _)) = self.tls.as_mut() else {
+                panic!("Invalid state")
+            };
+
+            if session.wants_write() {
+                let mut out = &mut buf.send[..];
+                self.request_len = session
+                    .write_tls(&mut out)
+                    .expect("Could not write TLS handshake");
+                self.make_send(sqe, buf);
+                self.state = TaskState::TlsSend;
+                return;
+            }
+
+            if session.is_handshaking() {
+                io_uring_prep_read(
+                    sqe,
+                    self.fd,
+                    buf.receive.as_mut_ptr() as *mut c_void,
+                    buf.receive.len() as u32,
+                    0,
+                );
+                self.state = TaskState::TlsReceive;
+                return;
+            }
 
-                    self.state = TaskState::Send;
+            let Some((session, started)) = self.tls.take() else {
+                panic!("Invalid state")
+            };
+            // Kernel support was checked upfront, what is left is the server picking a
+            // cipher the kernel does not know
+            if tls::install(self.fd, *session).is_err() {
+                self.tls_failed(sqe, stats);
+                return;
+            }
+            stats.new_tls_handshake(now.saturating_duration_since(started));
+
+            self.connected(sqe, buf, stats, now);
+        }
+    }
+
+    unsafe fn tls_failed(&mut self, sqe: *mut io_uring_sqe, stats: &mut Statistics) {
+        unsafe {
+            self.tls = None;
+            stats.increment_tls_failures();
+            self.make_reconnect(sqe);
+        }
+    }
+
+    /// Connection is ready for requests, wait on start gate first if there is one
+    unsafe fn ready(
+        &mut self,
+        sqe: *mut io_uring_sqe,
+        buf: &mut TaskBuf<'_>,
+        stats: &mut Statistics,
+        now: &Instant,
+    ) {
+        unsafe {
+            if let Some(gate) = self.gate {
+                io_uring_prep_poll_add(sqe, gate, libc::POLLIN as u32);
+                self.state = TaskState::Gated;
+                return;
+            }
+
+            self.send_first(sqe, buf, stats, now);
+        }
+    }
+
+    /// Drop the connection and start over with a new socket
+    unsafe fn make_reconnect(&mut self, sqe: *mut io_uring_sqe) {
+        unsafe {
+            io_uring_prep_close(sqe, self.fd);
+            self.received = 0;
+            self.state = TaskState::NewSock;
+        }
+    }
+
+    unsafe fn make_handshake_read(&mut self, sqe: *mut io_uring_sqe, buf: &mut TaskBuf<'_>) {
+        unsafe {
+            let free = &mut buf.receive[self.received..];
+            io_uring_prep_read(
+                sqe,
+                self.fd,
+                free.as_mut_ptr() as *mut c_void,
+                free.len() as u32,
+                0,
+            );
+            self.state = TaskState::HandshakeReply;
+        }
+    }
+
+    /// Read next response, with a linked timeout if one is configured
+    unsafe fn make_receive(
+        &mut self,
+        sqe: *mut io_uring_sqe,
+        ring: &mut ThreadIo,
+        buf: &mut TaskBuf<'_>,
+    ) {
+        unsafe {
+            // Continues after whatever part of the response is in already
+            let free = &mut buf.receive[self.received..];
+            match self.stamps.as_mut() {
+                Some(stamps) => {
+                    let msg = stamps.prepare(free);
+                    io_uring_prep_recvmsg(sqe, self.fd, msg as *mut msghdr, 0);
                 }
+                None => io_uring_prep_read(
+                    sqe,
+                    self.fd,
+                    free.as_mut_ptr() as *mut c_void,
+                    free.len() as u32,
+                    0,
+                ),
             }
+
+            if let Some(timeout) = get_settings().timeout.as_ref() {
+                io_uring_sqe_set_flags(sqe, IOSQE_IO_LINK);
+                let timeout_sqe = ring.push();
+                io_uring_prep_link_timeout(timeout_sqe, timeout, 0);
+                io_uring_sqe_set_data64(timeout_sqe, self.index as u64 | SIDE_FLAG);
+            }
+
+            if let Some(opt) = get_settings().quickack.as_ref() {
+                // Set once it would only cover the first ACKs, kernels without socket
+                // commands fail this and keep what was set on connect
+                let opt_sqe = ring.push();
+                prep_sock_opt(opt_sqe, self.fd, opt);
+                io_uring_sqe_set_flags(opt_sqe, IOSQE_CQE_SKIP_SUCCESS);
+                io_uring_sqe_set_data64(opt_sqe, self.index as u64 | SIDE_FLAG);
+            }
+
+            self.state = TaskState::Send;
+        }
+    }
+
+    /// Check response against the request according to `--validate`, framed tells whether
+    /// it came in whole
+    fn validate(&mut self, framed: Result<(), Failure>, buf: &TaskBuf<'_>) -> Result<(), Failure> {
+        let settings = get_settings();
+        if settings.validate == Validation::None {
+            return Ok(());
+        }
+
+        framed?;
+
+        if settings.header {
+            let header = RequestHeader::read(buf.receive);
+            // UDP sequence numbers went through the tracker already
+            let sequence_ok =
+                self.sequence.is_some() || header.sequence.wrapping_add(1) == self.sent;
+            if header.connection != self.connection || !sequence_ok {
+                return Err(Failure::Header);
+            }
+        }
+
+        match settings.validate {
+            Validation::Full => {
+                let from = self.payload_start();
+                self.workload.validate(
+                    &buf.send[from..self.request_len],
+                    &buf.receive[from..self.received],
+                )
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Where payload starts, bytes before it are header or sequence number
+    fn payload_start(&self) -> usize {
+        if get_settings().header {
+            HEADER_LEN
+        } else if self.sequence.is_some() {
+            SEQUENCE_LEN
+        } else {
+            0
+        }
+    }
+
+    /// Let workload write next request and put header or sequence number in front of it
+    fn build_request(&mut self, buf: &mut TaskBuf<'_>, stats: &mut Statistics) {
+        let from = self.payload_start();
+        self.request_len = from + self.workload.request(&mut buf.send[from..]);
+        self.stamp_sequence(buf, stats, true);
+    }
+
+    /// Write header, or just the sequence number for UDP without one, into first bytes of request
+    fn stamp_sequence(&mut self, buf: &mut TaskBuf<'_>, stats: &mut Statistics, new_request: bool) {
+        let sequence = match self.sequence.as_mut() {
+            Some(tracker) => {
+                stats.increment_datagrams_sent();
+                tracker.attempt(new_request)
+            }
+            None => self.sent,
+        };
+        self.sent += 1;
+
+        if get_settings().header {
+            RequestHeader::new(sequence, self.connection).write(buf.send);
+        } else if self.sequence.is_some() {
+            buf.send[..SEQUENCE_LEN].copy_from_slice(&sequence.to_le_bytes());
+        }
+    }
+
+    unsafe fn send_first(
+        &mut self,
+        sqe: *mut io_uring_sqe,
+        buf: &mut TaskBuf<'_>,
+        stats: &mut Statistics,
+        now: &Instant,
+    ) {
+        unsafe {
+            if let Some(pace) = self.pace.as_mut() {
+                pace.next = *now;
+            }
+
+            self.build_request(buf, stats);
+            self.workload.sent(*now);
+            self.make_send(sqe, buf);
+
+            self.send_time = Some(*now);
+
+            self.state = TaskState::Receive;
+        }
+    }
+
+    unsafe fn send_next(
+        &mut self,
+        sqe: *mut io_uring_sqe,
+        buf: &mut TaskBuf<'_>,
+        stats: &mut Statistics,
+        send_time: Instant,
+    ) {
+        unsafe {
+            self.send_time = Some(send_time);
+
+            self.build_request(buf, stats);
+            self.workload.sent(send_time);
+            self.make_send(sqe, buf);
+
+            self.state = TaskState::Receive;
+        }
+    }
+
+    unsafe fn make_send(&self, sqe: *mut io_uring_sqe, buf: &TaskBuf<'_>) {
+        unsafe {
+            let data = buf.send.as_ptr() as *const c_void;
+            // kTLS has to copy into records anyway and refuses zero copy sends
+            if self.tls_config.is_some() {
+                io_uring_prep_send(sqe, self.fd, data, self.request_len, 0);
+            } else {
+                io_uring_prep_send_zc(sqe, self.fd, data, self.request_len, 0, 0);
+            }
+        }
+    }
+
+    pub unsafe fn make_sock_opt(&mut self, sqe: *mut io_uring_sqe, index: usize) {
+        unsafe {
+            prep_sock_opt(sqe, self.fd, &get_settings().sock_opts[index]);
         }
     }
 
@@ -307,3 +971,19 @@ impl Task {
         }
     }
 }
+
+/// Opt has to outlive the SQE, settings do
+unsafe fn prep_sock_opt(sqe: *mut io_uring_sqe, fd: RawFd, opt: &SockOpt) {
+    unsafe {
+        let (optval, optlen) = opt.raw();
+        io_uring_prep_cmd_sock(
+            sqe,
+            SOCKET_URING_OP_SETSOCKOPT as i32,
+            fd,
+            opt.level,
+            opt.name,
+            optval,
+            optlen,
+        );
+    }
+}
@@ -0,0 +1,22 @@
+//! Fixtures shared by workload tests
+
+use std::sync::Arc;
+
+use crate::{
+    settings::{self, Settings},
+    workload::{Frame, Workload},
+};
+
+/// Workload of connection 1 set up the way workers do it under given settings
+pub fn connection<W: Workload + Default>(settings: Settings) -> W {
+    settings::enter(Arc::new(settings));
+    W::default().for_connection(1)
+}
+
+/// Every read cut short of the whole response has to wait for more
+pub fn assert_waits_for_all<W: Workload>(workload: &W, request: &[u8], response: &[u8]) {
+    for split in 0..response.len() {
+        let framed = workload.frame(request, &response[..split]);
+        assert_eq!(framed, Frame::Incomplete, "at {split}");
+    }
+}
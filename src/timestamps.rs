@@ -0,0 +1,93 @@
+use std::{
+    mem::zeroed,
+    os::{fd::RawFd, raw::c_void},
+    ptr,
+    time::Duration,
+};
+
+use libc::{
+    c_int, cmsghdr, iovec, msghdr, timespec, CMSG_DATA, CMSG_FIRSTHDR, CMSG_NXTHDR, MSG_DONTWAIT,
+    MSG_ERRQUEUE, SCM_TIMESTAMPING, SOF_TIMESTAMPING_OPT_TSONLY, SOF_TIMESTAMPING_RX_SOFTWARE,
+    SOF_TIMESTAMPING_SOFTWARE, SOF_TIMESTAMPING_TX_SOFTWARE, SOL_SOCKET,
+};
+
+/// SO_TIMESTAMPING flags asking for software send and receive timestamps
+pub const TIMESTAMPING_FLAGS: c_int = (SOF_TIMESTAMPING_SOFTWARE
+    | SOF_TIMESTAMPING_RX_SOFTWARE
+    | SOF_TIMESTAMPING_TX_SOFTWARE
+    | SOF_TIMESTAMPING_OPT_TSONLY) as c_int;
+
+/// Enough for SCM_TIMESTAMPING plus the extended error that comes with TX timestamps
+const CONTROL_LEN: usize = 256;
+
+/// recvmsg state of a task reading kernel timestamps
+///
+/// Kernel writes into it until the CQE arrives so it has to stay boxed in place
+pub struct KernelStamps {
+    msg: msghdr,
+    iov: iovec,
+    control: [u8; CONTROL_LEN],
+}
+
+impl KernelStamps {
+    pub fn new() -> Box<Self> {
+        Box::new(unsafe { zeroed() })
+    }
+
+    /// Point msghdr at buf, result is what recvmsg SQE should get
+    pub fn prepare(&mut self, buf: &mut [u8]) -> *mut msghdr {
+        self.iov = iovec {
+            iov_base: buf.as_mut_ptr() as *mut c_void,
+            iov_len: buf.len(),
+        };
+        self.msg = unsafe { zeroed() };
+        self.msg.msg_iov = &raw mut self.iov;
+        self.msg.msg_iovlen = 1;
+        self.msg.msg_control = self.control.as_mut_ptr() as *mut c_void;
+        self.msg.msg_controllen = CONTROL_LEN;
+        &raw mut self.msg
+    }
+
+    /// When kernel got the message last recvmsg returned
+    pub fn received(&self) -> Option<Duration> {
+        unsafe { find_stamp(&self.msg) }
+    }
+
+    /// When kernel sent out the newest request
+    ///
+    /// TX timestamps pile up on the error queue, all of them are drained so it does not grow.
+    /// That takes a recvmsg per queued stamp plus the one that finds the queue empty
+    pub fn sent(&self, fd: RawFd) -> Option<Duration> {
+        let mut newest = None;
+        loop {
+            let mut control = [0u8; CONTROL_LEN];
+            let mut msg: msghdr = unsafe { zeroed() };
+            msg.msg_control = control.as_mut_ptr() as *mut c_void;
+            msg.msg_controllen = CONTROL_LEN;
+
+            let out = unsafe { libc::recvmsg(fd, &mut msg, MSG_ERRQUEUE | MSG_DONTWAIT) };
+            if out < 0 {
+                return newest;
+            }
+
+            if let Some(stamp) = unsafe { find_stamp(&msg) } {
+                newest = Some(stamp);
+            }
+        }
+    }
+}
+
+/// First software timestamp of a SCM_TIMESTAMPING message, as time since epoch
+unsafe fn find_stamp(msg: &msghdr) -> Option<Duration> {
+    unsafe {
+        let mut cmsg: *mut cmsghdr = CMSG_FIRSTHDR(msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == SOL_SOCKET && (*cmsg).cmsg_type == SCM_TIMESTAMPING {
+                let stamp: timespec = ptr::read_unaligned(CMSG_DATA(cmsg) as *const timespec);
+                return Some(Duration::new(stamp.tv_sec as u64, stamp.tv_nsec as u32));
+            }
+            cmsg = CMSG_NXTHDR(msg, cmsg);
+        }
+        None
+    }
+}
@@ -0,0 +1,213 @@
+//! TLS client connections whose records are sealed and opened by the kernel (kTLS)
+//!
+//! Handshake runs in userspace through rustls while a connection is set up, its bytes go
+//! through the ring like any other. Once it is done the session keys are handed to the socket
+//! and every send and read after that is plain as far as tasks are concerned.
+
+use std::{
+    io,
+    os::{fd::RawFd, raw::c_void},
+    sync::Arc,
+};
+
+use rustls::{
+    client::{
+        danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+        Resumption,
+    },
+    crypto::{ring, verify_tls12_signature, verify_tls13_signature, CryptoProvider},
+    pki_types::{CertificateDer, ServerName, UnixTime},
+    version::TLS12,
+    ClientConfig, ClientConnection, ConnectionTrafficSecrets, DigitallySignedStruct,
+    SignatureScheme,
+};
+
+use crate::settings::get_settings;
+
+#[derive(Clone, Debug, Default)]
+pub struct TlsOptions {
+    /// Name sent as SNI, without one the target address is used and no SNI goes out
+    pub server_name: Option<String>,
+}
+
+/// Config every connection of a thread shares
+///
+/// Only TLS 1.2 is offered, TLS 1.3 servers send session tickets after the handshake and
+/// kTLS fails plain reads on records that are not application data. Certificates are not
+/// checked, benchmark targets run on self-signed ones more often than not.
+pub fn client_config() -> Arc<ClientConfig> {
+    let provider = Arc::new(ring::default_provider());
+    let mut config = ClientConfig::builder_with_provider(provider.clone())
+        .with_protocol_versions(&[&TLS12])
+        .expect("Crypto provider does not support TLS 1.2")
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(AnyCertificate(provider)))
+        .with_no_client_auth();
+    config.enable_secret_extraction = true;
+    config.resumption = Resumption::disabled();
+    Arc::new(config)
+}
+
+/// New session for a connection that just got connected
+pub fn connect(config: &Arc<ClientConfig>) -> ClientConnection {
+    let settings = get_settings();
+    let name = match settings
+        .tls
+        .as_ref()
+        .and_then(|tls| tls.server_name.clone())
+    {
+        Some(name) => ServerName::try_from(name).expect("Invalid TLS server name"),
+        None => ServerName::from(settings.target.ip()),
+    };
+    ClientConnection::new(config.clone(), name).expect("Could not start TLS session")
+}
+
+/// Make sure the kernel can take sessions over, done once before any connection is made
+///
+/// An unconnected socket is enough: the tls ULP gets loaded on demand and only then turns the
+/// socket down for not being connected, a kernel without it does not know the name at all
+pub fn check_kernel() -> io::Result<()> {
+    let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let res = setsockopt(fd, libc::SOL_TCP, libc::TCP_ULP, b"tls");
+    unsafe { libc::close(fd) };
+
+    match res {
+        Err(err) if err.raw_os_error() == Some(libc::ENOTCONN) => Ok(()),
+        res => res,
+    }
+}
+
+/// Hand keys of a finished handshake to the socket
+pub fn install(fd: RawFd, session: ClientConnection) -> io::Result<()> {
+    let secrets = session
+        .dangerous_extract_secrets()
+        .map_err(io::Error::other)?;
+
+    setsockopt(fd, libc::SOL_TCP, libc::TCP_ULP, b"tls")?;
+    let (seq, secrets_tx) = secrets.tx;
+    set_crypto(fd, libc::TLS_TX, seq, secrets_tx)?;
+    let (seq, secrets_rx) = secrets.rx;
+    set_crypto(fd, libc::TLS_RX, seq, secrets_rx)
+}
+
+/// Kernel wants the 12 byte nonce of AES-GCM split into 4 bytes of salt and 8 bytes of IV
+fn set_crypto(
+    fd: RawFd,
+    direction: i32,
+    seq: u64,
+    secrets: ConnectionTrafficSecrets,
+) -> io::Result<()> {
+    let info = |cipher_type| libc::tls_crypto_info {
+        version: libc::TLS_1_2_VERSION,
+        cipher_type,
+    };
+    let rec_seq = seq.to_be_bytes();
+
+    match secrets {
+        ConnectionTrafficSecrets::Aes128Gcm { key, iv } => {
+            let (salt, iv) = iv.as_ref().split_at(4);
+            let crypto = libc::tls12_crypto_info_aes_gcm_128 {
+                info: info(libc::TLS_CIPHER_AES_GCM_128),
+                iv: iv.try_into().unwrap(),
+                key: key.as_ref().try_into().unwrap(),
+                salt: salt.try_into().unwrap(),
+                rec_seq,
+            };
+            setsockopt(fd, libc::SOL_TLS, direction, &crypto)
+        }
+        ConnectionTrafficSecrets::Aes256Gcm { key, iv } => {
+            let (salt, iv) = iv.as_ref().split_at(4);
+            let crypto = libc::tls12_crypto_info_aes_gcm_256 {
+                info: info(libc::TLS_CIPHER_AES_GCM_256),
+                iv: iv.try_into().unwrap(),
+                key: key.as_ref().try_into().unwrap(),
+                salt: salt.try_into().unwrap(),
+                rec_seq,
+            };
+            setsockopt(fd, libc::SOL_TLS, direction, &crypto)
+        }
+        ConnectionTrafficSecrets::Chacha20Poly1305 { key, iv } => {
+            let crypto = libc::tls12_crypto_info_chacha20_poly1305 {
+                info: info(libc::TLS_CIPHER_CHACHA20_POLY1305),
+                iv: iv.as_ref().try_into().unwrap(),
+                key: key.as_ref().try_into().unwrap(),
+                salt: [],
+                rec_seq,
+            };
+            setsockopt(fd, libc::SOL_TLS, direction, &crypto)
+        }
+        _ => Err(io::Error::other(
+            "Cipher suite is not supported by kernel TLS",
+        )),
+    }
+}
+
+fn setsockopt<T>(fd: RawFd, level: i32, name: i32, value: &T) -> io::Result<()> {
+    let res = unsafe {
+        libc::setsockopt(
+            fd,
+            level,
+            name,
+            value as *const T as *const c_void,
+            size_of::<T>() as libc::socklen_t,
+        )
+    };
+    if res == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Takes any certificate but still checks handshake signatures, those are what proves the
+/// server holds the key
+#[derive(Debug)]
+struct AnyCertificate(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for AnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
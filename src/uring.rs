@@ -31,9 +31,9 @@ impl ThreadIo {
     }
 
     /// Get space in ring for next SQE
-    /// 
+    ///
     /// # SAFETY
-    /// 
+    ///
     /// io_uring_sqe can not be written to once ThreadIo goes out of scope
     #[inline]
     pub unsafe fn push(&mut self) -> *mut io_uring_sqe {
@@ -53,6 +53,23 @@ impl ThreadIo {
         sqe
     }
 
+    /// Make sure next n pushes land in the same submission
+    ///
+    /// Needed for linked SQEs as a link can not span across submissions
+    #[inline]
+    pub fn reserve(&mut self, n: c_uint) {
+        unsafe {
+            if io_uring_sq_space_left(&raw mut self.ring) < n {
+                io_uring_submit(&raw mut self.ring);
+            }
+        }
+    }
+
+    pub fn submit(&mut self) {
+        let out = unsafe { io_uring_submit(&raw mut self.ring) };
+        assert!(out >= 0, "Error while submitting");
+    }
+
     #[inline]
     pub fn wait_for_more(&mut self, out_buf: &mut VecDeque<io_uring_cqe>) -> Instant {
         unsafe {
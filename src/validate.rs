@@ -0,0 +1,58 @@
+/// How responses are checked against the request they answer
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Validation {
+    /// Byte for byte comparison with the request
+    #[default]
+    Full,
+    /// Only response length has to match
+    Length,
+    /// Length and request header, turns the header on
+    Header,
+    /// Anything that arrives is a right result, bar UDP sequence numbers that were never sent
+    None,
+}
+
+impl Validation {
+    pub fn parse(value: &str) -> Option<Self> {
+        Some(match value {
+            "full" => Self::Full,
+            "length" => Self::Length,
+            "header" => Self::Header,
+            "none" => Self::None,
+            _ => return None,
+        })
+    }
+}
+
+/// Why a response was not a right result
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Failure {
+    Length,
+    /// Wrong connection id or sequence number
+    Header,
+    Content,
+    /// Server answered with an error
+    Reply,
+    /// Server closed the connection instead of answering
+    Closed,
+}
+
+impl Failure {
+    pub const ALL: [Failure; 5] = [
+        Failure::Length,
+        Failure::Header,
+        Failure::Content,
+        Failure::Reply,
+        Failure::Closed,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Failure::Length => "length",
+            Failure::Header => "header",
+            Failure::Content => "content",
+            Failure::Reply => "error reply",
+            Failure::Closed => "closed",
+        }
+    }
+}
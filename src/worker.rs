@@ -1,61 +1,329 @@
 use std::{
     collections::VecDeque,
+    os::{fd::RawFd, raw::c_void},
+    sync::{Arc, Condvar, Mutex},
     thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
 
 use liburing_rs::*;
 
 use crate::{
-    settings::get_settings,
-    stats::{self, Statistics},
-    tasks,
+    settings::{self, get_settings},
+    stats::Statistics,
+    stop, tasks,
     uring::ThreadIo,
+    workload::Workload,
 };
 
-pub fn burn() {
-    let settings = get_settings();
+/// User data of the timeout ending the benchmark
+const BURN_DONE: u64 = u64::MAX;
+/// User data of the timeout marking end of ramp up
+const RAMP_DONE: u64 = u64::MAX - 1;
+/// User data of the poll on stop eventfd
+const STOPPED: u64 = u64::MAX - 2;
+/// User data of the timeout bounding connection setup before start gate
+const SETUP_DONE: u64 = u64::MAX - 3;
+
+/// Parameters that can differ between runs done by one process
+#[derive(Clone, Copy, Debug)]
+pub struct Round {
+    pub burn_time: __kernel_timespec,
+    pub rate: Option<f64>,
+    /// Successful exchanges across all workers after which the run ends
+    pub requests: Option<u64>,
+}
+
+/// Holds back first requests until every worker has all of its connections up
+///
+/// Tasks poll the eventfd once connected, last worker to arrive makes it readable
+struct StartGate {
+    /// Workers that have not arrived yet
+    remaining: Mutex<usize>,
+    opened: Condvar,
+    fd: RawFd,
+}
+
+impl StartGate {
+    fn new(threads: usize) -> Self {
+        let fd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC) };
+        assert!(fd >= 0, "Could not create eventfd");
+        Self {
+            remaining: Mutex::new(threads),
+            opened: Condvar::new(),
+            fd,
+        }
+    }
+
+    fn arrive(&self) {
+        // Poisoned only by a panic in here, count goes on regardless
+        let mut remaining = self.remaining.lock().unwrap_or_else(|x| x.into_inner());
+        *remaining -= 1;
+        if *remaining > 0 {
+            return;
+        }
+
+        let one: u64 = 1;
+        let out = unsafe {
+            libc::write(
+                self.fd,
+                &one as *const u64 as *const c_void,
+                size_of::<u64>(),
+            )
+        };
+        assert!(out >= 0, "Could not open start gate");
+        self.opened.notify_all();
+    }
+
+    fn wait_open(&self) {
+        let remaining = self.remaining.lock().unwrap_or_else(|x| x.into_inner());
+        drop(
+            self.opened
+                .wait_while(remaining, |remaining| *remaining > 0)
+                .unwrap_or_else(|x| x.into_inner()),
+        );
+    }
+}
+
+/// Place of one worker at the gate, dropping it unused (worker panicked) still lets the
+/// others through
+struct Ticket<'a>(Option<&'a StartGate>);
+
+impl Ticket<'_> {
+    fn wait(mut self) {
+        if let Some(gate) = self.0.take() {
+            gate.arrive();
+            gate.wait_open();
+        }
+    }
+}
+
+impl Drop for Ticket<'_> {
+    fn drop(&mut self) {
+        if let Some(gate) = self.0.take() {
+            gate.arrive();
+        }
+    }
+}
+
+impl Drop for StartGate {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+/// Run all worker threads once and merge what they measured
+pub fn run<W: Workload>(round: Round, workload: &W) -> Statistics {
+    let settings = settings::current();
+
+    // Ramp up staggers the start on purpose, gating it would undo that
+    let gate = settings
+        .ramp
+        .is_none()
+        .then(|| Arc::new(StartGate::new(settings.threads.get())));
+
     let join_handles: Vec<JoinHandle<Statistics>> = (0..settings.threads.get())
-        .map(|_| thread::spawn(worker))
+        .map(|index| {
+            let gate = gate.clone();
+            let workload = workload.clone();
+            let settings = settings.clone();
+            thread::spawn(move || {
+                settings::enter(settings);
+                worker(round, index, gate.as_deref(), &workload)
+            })
+        })
         .collect();
     let mut our_stats = Statistics::default();
     for ele in join_handles.into_iter() {
         our_stats.merge(ele.join().unwrap());
     }
-    stats::print_stats_final(&our_stats);
+    our_stats
 }
 
-pub fn worker() -> Statistics {
+fn worker<W: Workload>(
+    round: Round,
+    index: usize,
+    gate: Option<&StartGate>,
+    workload: &W,
+) -> Statistics {
     let settings = get_settings();
+    let setup_start = Instant::now();
+    let ticket = Ticket(gate);
 
     let mut stats = Statistics::default();
+
+    // Split requests evenly, first workers pick up the remainder
+    let threads = settings.threads.get() as u64;
+    let quota = round
+        .requests
+        .map(|requests| requests / threads + u64::from((index as u64) < requests % threads));
+    if quota == Some(0) {
+        ticket.wait();
+        return stats;
+    }
+
     let mut io = ThreadIo::create();
-    let mut tasking = tasks::ThreadLocalTasking::setup(&mut io, &mut stats);
+    let mut tasking = tasks::ThreadLocalTasking::setup(
+        &mut io,
+        &mut stats,
+        round.rate,
+        gate.map(|x| x.fd),
+        index,
+        workload,
+    );
 
-    unsafe {
-        let sqe = io.push();
-        io_uring_prep_timeout(sqe, &settings.burn_time as *const __kernel_timespec, 0, 0);
-        io_uring_sqe_set_data64(sqe, u64::MAX);
-    };
+    // Library users may not have Ctrl-C handling installed
+    if stop::fd() >= 0 {
+        unsafe {
+            let sqe = io.push();
+            io_uring_prep_poll_add(sqe, stop::fd(), libc::POLLIN as u32);
+            io_uring_sqe_set_data64(sqe, STOPPED);
+        }
+    }
 
     let mut out = VecDeque::new();
-    loop {
+    let mut last = false;
+
+    if gate.is_some() {
+        // Setup gets as long as the measured window, connections still missing by then join
+        // in late and keep counting their failures
+        unsafe {
+            let sqe = io.push();
+            io_uring_prep_timeout(sqe, &round.burn_time as *const __kernel_timespec, 0, 0);
+            io_uring_sqe_set_data64(sqe, SETUP_DONE);
+        }
+
+        let mut setup_done = false;
+        while !last && !setup_done && !tasking.all_connected() {
+            let current = io.wait_for_more(&mut out);
+            while let Some(cqe) = out.pop_front() {
+                match cqe.user_data {
+                    STOPPED => last = true,
+                    SETUP_DONE => setup_done = true,
+                    _ => tasking.progress(&mut io, cqe, &mut stats, &current),
+                }
+            }
+        }
+
+        stats.set_setup(setup_start.elapsed());
+    }
+    ticket.wait();
+
+    let mut measure_start = Instant::now();
+    let ramp = settings
+        .ramp
+        .filter(|_| settings.ramp_wait)
+        .map(__kernel_timespec::from);
+
+    match ramp.as_ref() {
+        Some(ramp) => unsafe {
+            let sqe = io.push();
+            io_uring_prep_timeout(sqe, ramp as *const __kernel_timespec, 0, 0);
+            io_uring_sqe_set_data64(sqe, RAMP_DONE);
+        },
+        None => arm_burn(&mut io, &round),
+    }
+
+    let mut measure_end = measure_start;
+    while !last {
         let current = io.wait_for_more(&mut out);
-        let mut last = false;
+        measure_end = current;
         while let Some(cqe) = out.pop_front() {
-            if cqe.user_data == u64::MAX {
+            if cqe.user_data == BURN_DONE || cqe.user_data == STOPPED {
                 last = true;
                 continue;
             }
 
-            tasking.progress(&mut io, cqe, &mut stats, &current);
-        }
+            // Setup timeout of a gate that opened before it ran out
+            if cqe.user_data == SETUP_DONE {
+                continue;
+            }
+
+            if cqe.user_data == RAMP_DONE {
+                stats.reset_measurements();
+                tasking.reset_requests();
+                measure_start = current;
+                arm_burn(&mut io, &round);
+                continue;
+            }
+
+            // Batch time is shared by every CQE in it, so later ones in a big batch look faster
+            // than they were. Reading the clock per CQE fixes that at cost of one vDSO call each
+            let now = if settings.precise {
+                Instant::now()
+            } else {
+                current
+            };
+            tasking.progress(&mut io, cqe, &mut stats, &now);
 
-        if last {
-            break;
+            if quota.is_some_and(|quota| stats.successful_returns() >= quota) {
+                last = true;
+                break;
+            }
         }
     }
 
+    stats.set_connections(tasking.requests_per_connection());
     drop(io);
 
+    stats.set_elapsed(measure_end.saturating_duration_since(measure_start));
     stats
 }
+
+/// Average cost of one clock read, what precise mode adds to every completion
+pub fn clock_cost() -> Duration {
+    const READS: u32 = 100_000;
+    let start = Instant::now();
+    for _ in 0..READS {
+        std::hint::black_box(Instant::now());
+    }
+    start.elapsed() / READS
+}
+
+/// Arm the timeout ending measured window, runs bound by request count have none
+fn arm_burn(io: &mut ThreadIo, round: &Round) {
+    if round.requests.is_some() {
+        return;
+    }
+
+    unsafe {
+        let sqe = io.push();
+        io_uring_prep_timeout(sqe, &round.burn_time as *const __kernel_timespec, 0, 0);
+        io_uring_sqe_set_data64(sqe, BURN_DONE);
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gate_opens_for_workers_that_panicked() {
+        let gate = Arc::new(StartGate::new(3));
+        let workers: Vec<_> = (0..3)
+            .map(|index| {
+                let gate = gate.clone();
+                thread::spawn(move || {
+                    let ticket = Ticket(Some(&gate));
+                    assert!(index != 0, "Worker died before the gate");
+                    ticket.wait();
+                })
+            })
+            .collect();
+
+        let panicked: Vec<bool> = workers.into_iter().map(|x| x.join().is_err()).collect();
+        assert_eq!(panicked, [true, false, false]);
+
+        let mut opened = 0u64;
+        let read = unsafe {
+            libc::read(
+                gate.fd,
+                &mut opened as *mut u64 as *mut c_void,
+                size_of::<u64>(),
+            )
+        };
+        assert_eq!((read, opened), (size_of::<u64>() as isize, 1));
+    }
+}
@@ -0,0 +1,198 @@
+use std::{io::Write, time::Instant};
+
+use crate::{
+    settings::get_settings,
+    stats::Statistics,
+    validate::{Failure, Validation},
+};
+
+/// What a workload makes of response bytes read so far
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Frame {
+    /// Response is this many bytes long, any other amount read is the wrong length and TCP
+    /// reconnects, unless the workload is multiplexed and read past it
+    Complete(usize),
+    /// Keep reading, UDP counts it as wrong length as a datagram can not continue and so does
+    /// TCP once the receive buffer is full, reconnecting as well
+    Incomplete,
+    /// Same as complete but the server turned the request down, counted as an error reply
+    /// whatever `--validate` says
+    Rejected(usize),
+    /// Response can not be parsed, TCP reconnects
+    Invalid,
+    /// Server is closing the connection, TCP reconnects
+    Reconnect,
+}
+
+/// Protocol spoken over every connection
+///
+/// Task state machine owns sockets, timing and retries and asks the workload for bytes to send
+/// and whether what came back is right. Every connection gets its own copy made by
+/// `for_connection`, workers are generic over it so nothing here goes through a vtable.
+pub trait Workload: Clone + Send + 'static {
+    /// Responses carry back the request as it was sent
+    ///
+    /// Only such workloads can use UDP sequence numbers and `--header`, both are written in
+    /// front of what `request` fills and the workload never sees them.
+    const ECHO: bool = false;
+
+    /// Responses to several requests interleave on one connection, like HTTP/2 streams
+    ///
+    /// Bytes read past a complete response are kept for the next exchange instead of dropping
+    /// the connection, and bytes the workload is done with are dropped after every read (see
+    /// `drain`). Tasks record no latency of their own, the workload reports one for every
+    /// request it sees end through `Statistics::new_measurement`.
+    const MULTIPLEXED: bool = false;
+
+    /// Copy of self for one connection, id is unique across all threads
+    fn for_connection(&self, connection: u64) -> Self;
+
+    /// Write request sent once connected before any other, like HTTP upgrade, returns its
+    /// length. Zero means there is none
+    fn handshake(&mut self, _buf: &mut [u8]) -> usize {
+        0
+    }
+
+    /// Look at handshake response read so far, anything but complete drops the connection
+    fn handshake_frame(&self, _received: &[u8]) -> Frame {
+        Frame::Complete(0)
+    }
+
+    /// Called with the handshake response once it came in whole
+    fn handshake_done(&mut self, _response: &[u8]) {}
+
+    /// Write next request into buf, returns how many bytes of it to send
+    ///
+    /// UDP resends the same bytes when a response times out
+    fn request(&mut self, buf: &mut [u8]) -> usize;
+
+    /// Called once the request is due to go out, latency of it counts from `at`
+    fn sent(&mut self, _at: Instant) {}
+
+    /// Look at response bytes read so far, request is the one they answer
+    fn frame(&self, request: &[u8], received: &[u8]) -> Frame;
+
+    /// Check a complete response, only called with `--validate full`
+    fn validate(&mut self, request: &[u8], response: &[u8]) -> Result<(), Failure>;
+
+    /// Requests one exchange carries, more than one when they are pipelined
+    fn requests_per_exchange(&self) -> u64 {
+        1
+    }
+
+    /// Called with every response that came in whole, validated or not
+    fn completed(&mut self, _response: &[u8], _stats: &mut Statistics) {}
+
+    /// Called after every read with response bytes so far and time of the read, before
+    /// `frame`. Lets multiplexed workloads see their streams end one by one
+    fn read(&mut self, _received: &[u8], _now: Instant, _stats: &mut Statistics) {}
+
+    /// Bytes at the start of what was read that `read` is done with, multiplexed workloads
+    /// only. Tasks drop them right after `read` to make room, so `frame` and the next `read`
+    /// see the bytes after them at the start
+    fn drain(&mut self) -> usize {
+        0
+    }
+}
+
+/// Built in workloads the command line can pick from
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WorkloadKind {
+    #[default]
+    Echo,
+    Redis,
+    Memcached,
+    WebSocket,
+    Http2,
+    /// Unary gRPC calls, run by `Http2` as well
+    Grpc,
+    /// Length prefixed messages, run by `Framed`
+    Length,
+    /// Delimited messages, run by `Framed` as well
+    Delimited,
+}
+
+/// Bytes of every key name, `Settings::check` keeps keys below `MAX_KEYS` so it holds
+pub const KEY_LEN: usize = 14;
+pub const MAX_KEYS: u64 = 10_000_000_000;
+
+/// Write name of key number n, same for every key/value workload
+pub fn write_key(out: &mut impl Write, key: u64) -> std::io::Result<()> {
+    write!(out, "key:{key:010}")
+}
+
+/// Sends pseudo random bytes and expects them back, what every example server does
+#[derive(Clone, Debug, Default)]
+pub struct Echo {
+    dumb_rand: u64,
+    validation: Validation,
+}
+
+impl Workload for Echo {
+    const ECHO: bool = true;
+
+    fn for_connection(&self, connection: u64) -> Self {
+        Self {
+            dumb_rand: connection,
+            validation: get_settings().validate,
+        }
+    }
+
+    fn request(&mut self, buf: &mut [u8]) -> usize {
+        let out: &mut [u64] = bytemuck::cast_slice_mut(buf);
+        for (index, ele) in out.iter_mut().enumerate() {
+            self.dumb_rand = self.dumb_rand.wrapping_add(*ele);
+            *ele ^= self.dumb_rand + (index as u64);
+        }
+        buf.len()
+    }
+
+    fn frame(&self, request: &[u8], received: &[u8]) -> Frame {
+        match self.validation {
+            // Waiting for a whole echo would stall on servers that do not send one, so modes
+            // that do not look at the bytes take the first read
+            Validation::None => Frame::Complete(received.len()),
+            Validation::Length | Validation::Header => Frame::Complete(request.len()),
+            _ if received.len() < request.len() => Frame::Incomplete,
+            _ => Frame::Complete(request.len()),
+        }
+    }
+
+    fn validate(&mut self, request: &[u8], response: &[u8]) -> Result<(), Failure> {
+        if response != request {
+            Err(Failure::Content)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn echo_with(validation: Validation) -> Echo {
+        Echo {
+            validation,
+            ..Echo::default()
+        }
+    }
+
+    #[test]
+    fn echo_waits_only_when_it_checks_bytes() {
+        let request = [7; 16];
+        let echo = echo_with(Validation::Full);
+        assert_eq!(echo.frame(&request, &request[..8]), Frame::Incomplete);
+        assert_eq!(echo.frame(&request, &request), Frame::Complete(16));
+
+        // First read is the response, a short one is the wrong length
+        for validation in [Validation::Length, Validation::Header] {
+            let echo = echo_with(validation);
+            assert_eq!(echo.frame(&request, &request[..8]), Frame::Complete(16));
+            assert_eq!(echo.frame(&request, &request), Frame::Complete(16));
+        }
+
+        let echo = echo_with(Validation::None);
+        assert_eq!(echo.frame(&request, &request[..1]), Frame::Complete(1));
+    }
+}
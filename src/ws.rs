@@ -0,0 +1,373 @@
+use std::io::{Cursor, Write};
+
+use crate::{
+    keys::SplitMix,
+    settings::{get_settings, Settings},
+    stats::Statistics,
+    validate::Failure,
+    workload::{Frame, Workload},
+};
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xa;
+const FIN: u8 = 0x80;
+const MASKED: u8 = 0x80;
+/// Control frames carry at most this much payload
+const MAX_CONTROL: usize = 125;
+
+/// Nonce from RFC 6455, server only has to hash it back so it does not need to be random
+const HANDSHAKE_KEY: &str = "dGhlIHNhbXBsZSBub25jZQ==";
+
+/// Binary messages to a WebSocket echo server, handshake included
+#[derive(Clone, Debug, Default)]
+pub struct WebSocket {
+    /// Upgrade request, built once
+    upgrade: Vec<u8>,
+    rng: SplitMix,
+    /// Payload of message in flight
+    message: Vec<u8>,
+    /// Payload of a ping that still needs its pong, it goes out with next message
+    pong: Option<Vec<u8>>,
+}
+
+/// Header of one frame sent by the server
+#[derive(Clone, Copy, Debug)]
+struct FrameHeader {
+    fin: bool,
+    opcode: u8,
+    /// Header bytes before payload
+    start: usize,
+    len: usize,
+}
+
+impl FrameHeader {
+    fn end(&self) -> usize {
+        self.start + self.len
+    }
+}
+
+impl WebSocket {
+    fn new(settings: &Settings, connection: u64) -> Self {
+        let upgrade = format!(
+            "GET {} HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Key: {HANDSHAKE_KEY}\r\nSec-WebSocket-Version: 13\r\n\r\n",
+            settings.path, settings.target
+        );
+        Self {
+            upgrade: upgrade.into_bytes(),
+            rng: SplitMix(connection),
+            message: vec![0; settings.value_size],
+            pong: None,
+        }
+    }
+}
+
+/// Bytes of the longest request given settings make, upgrade or a message behind a pong
+pub fn request_len(settings: &Settings) -> usize {
+    let ws = WebSocket::new(settings, 0);
+    let message = frame_len(MAX_CONTROL) + frame_len(ws.message.len());
+    ws.upgrade.len().max(message)
+}
+
+/// Bytes a client frame with given payload takes
+fn frame_len(payload: usize) -> usize {
+    let extended = match payload {
+        0..=125 => 0,
+        126..=0xffff => 2,
+        _ => 8,
+    };
+    2 + extended + 4 + payload
+}
+
+impl Workload for WebSocket {
+    fn for_connection(&self, connection: u64) -> Self {
+        Self::new(get_settings(), connection)
+    }
+
+    fn handshake(&mut self, buf: &mut [u8]) -> usize {
+        let mut out = Cursor::new(buf);
+        out.write_all(&self.upgrade)
+            .expect("WebSocket handshake does not fit into request buffer");
+        out.position() as usize
+    }
+
+    fn handshake_frame(&self, received: &[u8]) -> Frame {
+        let Some(end) = received.windows(4).position(|x| x == b"\r\n\r\n") else {
+            return Frame::Incomplete;
+        };
+
+        // Accept key is not checked, switching protocols is all a benchmark needs to know
+        if received.starts_with(b"HTTP/1.1 101") {
+            Frame::Complete(end + 4)
+        } else {
+            Frame::Invalid
+        }
+    }
+
+    fn request(&mut self, buf: &mut [u8]) -> usize {
+        for byte in self.message.iter_mut() {
+            *byte = self.rng.next_u64() as u8;
+        }
+
+        let mut out = Cursor::new(buf);
+        if let Some(pong) = self.pong.take() {
+            write_frame(&mut out, OP_PONG, &pong, &mut self.rng);
+        }
+        write_frame(&mut out, OP_BINARY, &self.message, &mut self.rng);
+        out.position() as usize
+    }
+
+    fn frame(&self, _request: &[u8], received: &[u8]) -> Frame {
+        let mut offset = 0;
+        loop {
+            let header = match parse_header(&received[offset..]) {
+                Ok(Some(header)) if offset + header.end() <= received.len() => header,
+                Ok(_) => return Frame::Incomplete,
+                Err(()) => return Frame::Invalid,
+            };
+            offset += header.end();
+
+            match header.opcode {
+                OP_CLOSE => return Frame::Reconnect,
+                OP_PING | OP_PONG if header.len <= MAX_CONTROL => {}
+                OP_CONTINUATION | OP_TEXT | OP_BINARY if header.fin => {
+                    return Frame::Complete(offset)
+                }
+                OP_CONTINUATION | OP_TEXT | OP_BINARY => {}
+                _ => return Frame::Invalid,
+            }
+        }
+    }
+
+    fn validate(&mut self, _request: &[u8], response: &[u8]) -> Result<(), Failure> {
+        // Echo may come back split into several frames
+        let mut offset = 0;
+        let mut echoed = 0;
+        while let Ok(Some(header)) = parse_header(&response[offset..]) {
+            if matches!(header.opcode, OP_CONTINUATION | OP_TEXT | OP_BINARY) {
+                let payload = &response[offset + header.start..offset + header.end()];
+                if self.message.get(echoed..echoed + payload.len()) != Some(payload) {
+                    return Err(Failure::Content);
+                }
+                echoed += payload.len();
+            }
+            offset += header.end();
+        }
+
+        if echoed != self.message.len() {
+            return Err(Failure::Length);
+        }
+        Ok(())
+    }
+
+    fn completed(&mut self, response: &[u8], _stats: &mut Statistics) {
+        let mut offset = 0;
+        while let Ok(Some(header)) = parse_header(&response[offset..]) {
+            if header.opcode == OP_PING {
+                let payload = &response[offset + header.start..offset + header.end()];
+                self.pong = Some(payload.to_vec());
+            }
+            offset += header.end();
+        }
+    }
+}
+
+/// Client frames are always masked
+fn write_frame(out: &mut Cursor<&mut [u8]>, opcode: u8, payload: &[u8], rng: &mut SplitMix) {
+    let mask = (rng.next_u64() as u32).to_ne_bytes();
+    let len = payload.len();
+
+    let written = (|| {
+        out.write_all(&[FIN | opcode])?;
+        match len {
+            0..=125 => out.write_all(&[MASKED | len as u8])?,
+            126..=0xffff => {
+                out.write_all(&[MASKED | 126])?;
+                out.write_all(&(len as u16).to_be_bytes())?;
+            }
+            _ => {
+                out.write_all(&[MASKED | 127])?;
+                out.write_all(&(len as u64).to_be_bytes())?;
+            }
+        }
+        out.write_all(&mask)?;
+        for (index, byte) in payload.iter().enumerate() {
+            out.write_all(&[byte ^ mask[index % 4]])?;
+        }
+        Ok::<_, std::io::Error>(())
+    })();
+    written.expect("WebSocket message does not fit into request buffer");
+}
+
+/// Header of the frame at the start of buf, None while not all of it is in
+fn parse_header(buf: &[u8]) -> Result<Option<FrameHeader>, ()> {
+    let [first, second, ..] = *buf else {
+        return Ok(None);
+    };
+    if second & MASKED != 0 {
+        // Servers must not mask
+        return Err(());
+    }
+
+    let (start, len) = match second & 0x7f {
+        126 => match buf.get(2..4) {
+            Some(x) => (4, u16::from_be_bytes(x.try_into().unwrap()) as usize),
+            None => return Ok(None),
+        },
+        127 => match buf.get(2..10) {
+            // Most significant bit has to be zero
+            Some(x) if x[0] & 0x80 != 0 => return Err(()),
+            Some(x) => (10, u64::from_be_bytes(x.try_into().unwrap()) as usize),
+            None => return Ok(None),
+        },
+        len => (2, len as usize),
+    };
+
+    Ok(Some(FrameHeader {
+        fin: first & FIN != 0,
+        opcode: first & 0x0f,
+        start,
+        len,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    fn server_frame(first: u8, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![first];
+        match payload.len() {
+            len @ 0..=125 => frame.push(len as u8),
+            len @ 126..=0xffff => {
+                frame.push(126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                frame.push(127);
+                frame.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    fn connection(value_size: usize) -> WebSocket {
+        let settings = Settings {
+            value_size,
+            ..Settings::default()
+        };
+        testing::connection(settings)
+    }
+
+    #[test]
+    fn header_lengths() {
+        for len in [0, 125, 126, 0xffff, 0x10000] {
+            let frame = server_frame(FIN | OP_BINARY, &vec![0; len]);
+            let start = frame.len() - len;
+            for split in 0..start {
+                assert_eq!(
+                    parse_header(&frame[..split]).map(|x| x.is_some()),
+                    Ok(false)
+                );
+            }
+            let header = parse_header(&frame[..start]).unwrap().unwrap();
+            assert_eq!(
+                (header.start, header.len, header.end()),
+                (start, len, frame.len())
+            );
+            assert!(header.fin);
+            assert_eq!(header.opcode, OP_BINARY);
+        }
+    }
+
+    #[test]
+    fn bad_headers() {
+        // Masked by the server, length with most significant bit set
+        assert!(parse_header(&[FIN | OP_BINARY, MASKED | 1, 0, 0, 0, 0, 0]).is_err());
+        let mut frame = vec![FIN | OP_BINARY, 127];
+        frame.extend_from_slice(&u64::MAX.to_be_bytes());
+        assert!(parse_header(&frame).is_err());
+    }
+
+    #[test]
+    fn message_waits_for_last_fragment() {
+        let ws = connection(4);
+        let mut response = server_frame(OP_PING, b"hi");
+        response.extend(server_frame(OP_BINARY, b"ab"));
+        response.extend(server_frame(FIN | OP_CONTINUATION, b"cd"));
+        testing::assert_waits_for_all(&ws, &[], &response);
+        assert_eq!(ws.frame(&[], &response), Frame::Complete(response.len()));
+    }
+
+    #[test]
+    fn control_frames() {
+        let ws = connection(4);
+        let close = server_frame(FIN | OP_CLOSE, &[0x03, 0xe8]);
+        assert_eq!(ws.frame(&[], &close), Frame::Reconnect);
+        let ping = server_frame(FIN | OP_PING, &[0; MAX_CONTROL + 1]);
+        assert_eq!(ws.frame(&[], &ping), Frame::Invalid);
+        assert_eq!(ws.frame(&[], &server_frame(FIN | 0x3, b"")), Frame::Invalid);
+    }
+
+    #[test]
+    fn echo_is_validated_and_ping_answered() {
+        let mut ws = connection(4);
+        let mut buf = [0; 256];
+        let len = ws.request(&mut buf);
+        assert_eq!(len, frame_len(4));
+
+        let message = ws.message.clone();
+        let mut response = server_frame(OP_BINARY, &message[..1]);
+        response.extend(server_frame(FIN | OP_PING, b"hi"));
+        response.extend(server_frame(FIN | OP_CONTINUATION, &message[1..]));
+        assert_eq!(ws.validate(&[], &response), Ok(()));
+
+        let short = server_frame(FIN | OP_BINARY, &message[..3]);
+        assert_eq!(ws.validate(&[], &short), Err(Failure::Length));
+        let wrong = server_frame(FIN | OP_BINARY, &[!message[0]; 4]);
+        assert_eq!(ws.validate(&[], &wrong), Err(Failure::Content));
+
+        ws.completed(&response, &mut Statistics::default());
+        let len = ws.request(&mut buf);
+        assert_eq!(len, frame_len(2) + frame_len(4));
+        assert_eq!(buf[0], FIN | OP_PONG);
+        let mask = &buf[2..6];
+        let pong: Vec<u8> = buf[6..8].iter().zip(mask).map(|(x, m)| x ^ m).collect();
+        assert_eq!(pong, b"hi");
+    }
+
+    #[test]
+    fn handshake() {
+        let ws = connection(4);
+        let response = b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\r\n";
+        for split in 0..response.len() {
+            assert_eq!(ws.handshake_frame(&response[..split]), Frame::Incomplete);
+        }
+        assert_eq!(
+            ws.handshake_frame(response),
+            Frame::Complete(response.len())
+        );
+        assert_eq!(
+            ws.handshake_frame(b"HTTP/1.1 400 Bad Request\r\n\r\n"),
+            Frame::Invalid
+        );
+    }
+
+    #[test]
+    fn longest_request_fits() {
+        let mut ws = connection(70_000);
+        ws.pong = Some(vec![0; MAX_CONTROL]);
+        let settings = Settings {
+            value_size: 70_000,
+            ..Settings::default()
+        };
+        let mut buf = vec![0; request_len(&settings)];
+        assert_eq!(ws.request(&mut buf), buf.len());
+    }
+}